@@ -1,22 +1,106 @@
 use anchor_lang::prelude::*;
-use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::associated_token::{get_associated_token_address_with_program_id, AssociatedToken};
 
-// --- Token-v1 vs Token-2022 toggle -----------------------------------------
-#[cfg(not(feature = "token-2022"))]
-use anchor_spl::token::{self as token_i, Mint, Token, TokenAccount, Transfer};
-
-#[cfg(feature = "token-2022")]
-use anchor_spl::token_2022::{self as token_i, Mint, Token, TokenAccount, Transfer};
+// --- SPL Token or Token-2022, chosen at runtime from each mint's owner ---------
+use anchor_spl::token_interface::{
+    self as token_i, CloseAccount, Mint, TokenAccount, TokenInterface,
+};
+use anchor_spl::token_2022::spl_token_2022::{
+    self,
+    extension::{
+        default_account_state::DefaultAccountState,
+        interest_bearing_mint::InterestBearingConfig, non_transferable::NonTransferable,
+        permanent_delegate::PermanentDelegate, transfer_fee::TransferFeeConfig,
+        BaseStateWithExtensions, StateWithExtensions,
+    },
+    state::AccountState,
+};
 // ---------------------------------------------------------------------------
 
-// Pyth 0.8.0
-use pyth_sdk_solana::load_price_feed_from_account_info;
+// Pyth 0.8.0 (push feeds); pull updates are decoded locally, see `PriceUpdateV2`
+use pyth_sdk_solana::{load_price_feed_from_account_info, Price};
 
 declare_id!("3yU4CGvB2pDQPk2ACBSjy8JBTEnnvbdLS9U1couLPmVM");
 
 /// Fixed program seeds
 const SEED_VAULT: &[u8] = b"vault";
 const SEED_AUTH: &[u8] = b"authority";
+const SEED_RELEASE: &[u8] = b"release";
+const SEED_GOVERNANCE: &[u8] = b"governance";
+const SEED_AUCTION: &[u8] = b"auction";
+const SEED_ELIGIBILITY: &[u8] = b"eligibility";
+const SEED_MANUAL_PRICE: &[u8] = b"manual_price";
+const SEED_OBSERVATIONS: &[u8] = b"observations";
+
+/// Approval bitmap with every role set (bit0=custodian, bit1=borrower, bit2=lender)
+const ALL_ROLES_MASK: u8 = 0b111;
+
+/// Longest a release/governance proposal may stay open before it must be re-proposed
+const MAX_PROPOSAL_TTL_SECS: i64 = 7 * 86_400;
+
+/// Delay before risk/oracle changes take effect (default and upper bound; must stay < TTL)
+const DEFAULT_RISK_TIMELOCK_SECS: i64 = 86_400;
+const MAX_RISK_TIMELOCK_SECS: i64 = 3 * 86_400;
+
+/// pyth-solana-receiver program that owns pulled `PriceUpdateV2` accounts
+/// (rec5EKMGg6MxZYaMdyBfgwp4d5rB9T1VQH5pJv5LtFJ)
+const PYTH_RECEIVER_PROGRAM_ID: Pubkey = Pubkey::new_from_array([
+    12, 183, 250, 187, 82, 247, 166, 72, 187, 91, 49, 125, 154, 1, 139, 144, 87, 203, 2, 71,
+    116, 250, 254, 1, 230, 196, 223, 152, 204, 56, 88, 129,
+]);
+/// Anchor discriminator of `PriceUpdateV2` (sha256("account:PriceUpdateV2")[..8])
+const PRICE_UPDATE_V2_DISCRIMINATOR: [u8; 8] = [34, 241, 35, 99, 157, 126, 244, 205];
+
+/// Switchboard On-Demand program that owns `PullFeedAccountData` feeds
+/// (SBondMDrcV3K4kxZR1HNVT7osZxAHVHgYXL5Ze1oMUv)
+const SWITCHBOARD_ON_DEMAND_PROGRAM_ID: Pubkey = Pubkey::new_from_array([
+    6, 115, 189, 70, 242, 228, 126, 4, 241, 43, 217, 47, 183, 49, 150, 142, 205, 157, 151, 87,
+    194, 116, 218, 135, 71, 111, 70, 92, 4, 12, 101, 115,
+]);
+/// Anchor discriminator of `PullFeedAccountData`
+const SB_PULL_FEED_DISCRIMINATOR: [u8; 8] = [196, 27, 108, 196, 10, 215, 219, 40];
+/// Byte offsets into `PullFeedAccountData` (after the discriminator; repr(C), no padding),
+/// per switchboard-on-demand 0.3.8; the unit tests check them against a transcription
+const SB_RESULT_VALUE_OFFSET: usize = 2256; // CurrentResult.value (i128, 1e18)
+const SB_RESULT_STD_DEV_OFFSET: usize = 2272; // CurrentResult.std_dev (i128, 1e18)
+const SB_RESULT_SUBMISSION_IDX_OFFSET: usize = 2353; // CurrentResult.submission_idx (u8)
+const SB_RESULT_SLOT_OFFSET: usize = 2360; // CurrentResult.slot (u64)
+const SB_SUBMISSION_TS_OFFSET: usize = 2944; // submission_timestamps ([i64; 32])
+const SB_PULL_FEED_LEN: usize = 3200;
+/// Switchboard values carry 18 decimals; they are rescaled to Pyth's usual 1e-8
+const SB_EXPO: i32 = -8;
+const SB_RESCALE: i128 = 10_000_000_000;
+
+/// Most oracle sources a vault aggregates
+const MAX_ORACLE_SOURCES: usize = 3;
+
+/// TWAP ring buffer length and the exponent observations are stored at
+const TWAP_CAPACITY: usize = 32;
+const TWAP_EXPO: i32 = -8;
+
+/// Most collateral mints a single vault can hold
+const MAX_BASKET_ASSETS: usize = 4;
+
+/// Collateral mint risks refused unless all parties accept them at initialization
+/// (bits of `VaultState::accepted_mint_risks`)
+const MINT_RISK_PERMANENT_DELEGATE: u8 = 1 << 0;
+const MINT_RISK_FREEZE_AUTHORITY: u8 = 1 << 1;
+const MINT_RISK_DEFAULT_FROZEN: u8 = 1 << 2;
+const MINT_RISK_NON_TRANSFERABLE: u8 = 1 << 3;
+const MINT_RISK_ALL: u8 = 0b1111;
+
+/// Token-2022 mint TLV layout: extensions start after the 165-byte padded base and the
+/// account-type byte. Only read by offset for ScaledUiAmount, which spl-token-2022 0.9 lacks.
+const MINT_TLV_START: usize = 166;
+const MINT_ACCOUNT_TYPE: u8 = 1;
+const EXT_SCALED_UI_AMOUNT: u16 = 25;
+/// Year length the Token-2022 interest-bearing extension compounds over (365.24 days)
+const INTEREST_BEARING_SECS_PER_YEAR: f64 = 31_556_736.0;
+/// Fixed-point scale of a raw -> UI amount multiplier
+const UI_MULTIPLIER_SCALE: f64 = 1e12;
+
+/// Simple-interest day count (actual/365)
+const SECS_PER_YEAR: u128 = 365 * 86_400;
 
 /// Token-denominated risk knobs used when Pyth is disabled (fallback)
 const DAILY_CAP_TOKENS: u64 = 1_000_000_000_000;       // per-day release cap (base units)
@@ -26,13 +110,16 @@ const MAX_SINGLE_RELEASE_TOKENS: u64 = 500_000_000_000; // per-tx max release (b
 pub mod tri_party_vault {
     use super::*;
 
-    /// Create the vault state PDA, derive the vault_authority PDA, and init vault ATA.
+    /// Create the vault state PDA, derive the vault_authority PDA, and init the vault ATA for
+    /// the first basket mint (further eligible mints join the basket on first deposit).
     pub fn initialize_vault(
         ctx: Context<InitializeVault>,
         custodian: Pubkey,
         borrower: Pubkey,
         lender: Pubkey,
         mint: Pubkey,
+        loan: LoanParams,
+        accepted_mint_risks: u8,
     ) -> Result<()> {
         // Reinit protection via `init` on the VaultState PDA.
         let state = &mut ctx.accounts.vault_state;
@@ -45,37 +132,124 @@ pub mod tri_party_vault {
         // Pin the passed mint to the provided mint account (belt & suspenders)
         require_keys_eq!(ctx.accounts.mint_account.key(), mint, ErrorCode::Unauthorized);
 
-        // Persist core state
-        state.mint = mint;
-        state.mint_decimals = ctx.accounts.mint_account.decimals;
+        // Mint policy: risky mints need an override signed by all three parties (borrower and
+        // lender already sign the loan terms)
+        require!(accepted_mint_risks & !MINT_RISK_ALL == 0, ErrorCode::InvalidMintRiskOverride);
+        if accepted_mint_risks != 0 {
+            let custodian_signer = ctx
+                .accounts
+                .custodian_signer
+                .as_ref()
+                .ok_or(ErrorCode::InvalidMintRiskOverride)?;
+            require_keys_eq!(custodian_signer.key(), custodian, ErrorCode::Unauthorized);
+        }
+        state.accepted_mint_risks = accepted_mint_risks;
+        check_mint_policy(accepted_mint_risks, &ctx.accounts.mint_account.to_account_info())?;
+
+        // Persist core state; the first mint opens the basket (no feed or haircut yet)
+        state.basket = vec![CollateralAsset {
+            mint,
+            decimals: ctx.accounts.mint_account.decimals,
+            token_program: ctx.accounts.token_program.key(),
+            ui_scaled: mint_ui_scaled(&ctx.accounts.mint_account.to_account_info())?,
+            price_feed: Pubkey::default(),
+            feed_id: [0; 32],
+            switchboard_feed: Pubkey::default(),
+            haircut_bps: 0,
+            amount_locked: 0,
+            last_price: 0,
+            last_expo: 0,
+            last_price_ts: 0,
+        }];
         state.custodian = custodian;
         state.borrower = borrower;
         state.lender = lender;
-        state.approvals_bitmap = 0;
-        state.amount_locked = 0;
         state.is_frozen = false;
+        state.frozen_at = 0;
+        state.release_nonce = 0;
+        state.open_release_proposals = 0;
+        state.open_aux_accounts = 0;
+        state.executor = Pubkey::default(); // none until the roles configure one
+        state.governance_nonce = 0;
+        state.roles_epoch = 0;
+        state.quote_epoch = 0;
 
-        // Default 2-of-3 threshold (future-proof; you can expose a setter later)
+        // Default 2-of-3 threshold; changed via a unanimous `SetThreshold` proposal
         state.threshold = 2;
+        // Every action inherits the threshold until the roles agree otherwise
+        state.quorum = QuorumPolicy::default();
+        state.risk_timelock_secs = DEFAULT_RISK_TIMELOCK_SECS;
 
         // Store the vault_authority bump for later CPI signer use (Anchor-generated field).
         let bump: u8 = ctx.bumps.vault_authority;
         state.vault_authority_bump = bump;
 
-        // Init daily cap trackers (token & USD)
-        state.last_cap_reset_ts = Clock::get()?.unix_timestamp;
+        // Whoever funded the accounts gets the rent back on close
+        state.rent_payer = ctx.accounts.payer.key();
+
+        // Loan ledger (borrower & lender co-sign the terms via the account constraints)
+        let now = Clock::get()?.unix_timestamp;
+        require_keys_eq!(
+            ctx.accounts.debt_mint_account.key(),
+            loan.debt_mint,
+            ErrorCode::Unauthorized
+        );
+        require!(loan.principal > 0, ErrorCode::InvalidLoanTerms);
+        require!(loan.maturity_ts > now, ErrorCode::InvalidLoanTerms);
+        state.loan = LoanTerms {
+            debt_mint: loan.debt_mint,
+            debt_decimals: ctx.accounts.debt_mint_account.decimals,
+            debt_token_program: *ctx.accounts.debt_mint_account.to_account_info().owner,
+            principal: loan.principal,
+            apr_bps: loan.apr_bps,
+            start_ts: now,
+            maturity_ts: loan.maturity_ts,
+            accrued_interest: 0,
+            last_accrual_ts: now,
+            accrual_carry: 0,
+        };
+
+        // Init daily cap trackers (token & quote)
+        state.last_cap_reset_ts = now;
         state.released_today = 0;
-        state.released_today_usd_1e6 = 0;
+        state.released_today_quote_1e6 = 0;
 
         // Default price config (disabled)
         state.price_config = PriceConfig {
             enabled: false,
-            sol_usdc_price_feed: Pubkey::default(),
             max_ltv_bps: 7000,                 // 70% LTV -> min collateral 30% retained
-            max_single_release_usd_1e6: 1_000_000_000, // 1,000 USDC
-            daily_cap_usd_1e6: 5_000_000_000,  // 5,000 USDC
+            max_single_release_quote_1e6: 1_000_000_000, // 1,000 quote units
+            daily_cap_quote_1e6: 5_000_000_000, // 5,000 quote units
             max_price_staleness_secs: 90,
+            maintenance_ratio_bps: 12_000,     // 120% collateral / debt
+            margin_cure_secs: 86_400,          // 24h to top up after a margin call
+            auction_premium_bps: 500,          // start 5% above oracle
+            auction_floor_bps: 8_000,          // decay to 80% of oracle
+            auction_duration_secs: 3_600,
+            liquidation_fee_bps: 200,          // 2% of the debt to the custodian
+            oracle_sources: [OracleSource::PythPush, OracleSource::None, OracleSource::None],
+            min_oracle_sources: 1,
+            max_oracle_deviation_bps: 200,     // sources must agree within 2% of the median
+            valuation_mode: ValuationMode::Spot,
+            twap_window_secs: 1_800,           // 30 min TWAP
+            twap_min_interval_secs: 60,        // at most one observation per minute
+            max_conf_bps: 200,                 // refuse releases when conf > 2% of price
+            max_price_move_bps: 1_000,         // trip on a >10% move ...
+            price_move_window_secs: 3_600,     // ... within an hour of the last accepted price
+            quote: QuoteConfig {
+                symbol: *b"USD\0\0\0\0\0",
+                cross_rate: false,             // debt mint is a USD stablecoin
+                price_feed: Pubkey::default(),
+                feed_id: [0; 32],
+                switchboard_feed: Pubkey::default(),
+            },
+            manual_max_staleness_secs: 86_400, // daily attestations
+            manual_max_change_bps: 1_000,      // 10% per window unless the lender co-signs
+            manual_change_window_secs: 86_400, // measured against the price a day ago at most
+            min_verification: VerificationLevel::Full,
         };
+        state.status = VaultStatus::Active;
+        state.margin_call_deadline = 0;
 
         // Extra runtime checks for PDAs/ATAs (ATA macro guarantees, but we assert anyway)
         require_keys_eq!(
@@ -91,18 +265,37 @@ pub mod tri_party_vault {
             borrower,
             lender
         });
+        emit!(LoanOpened {
+            debt_mint: loan.debt_mint,
+            principal: loan.principal,
+            apr_bps: loan.apr_bps,
+            start_ts: now,
+            maturity_ts: loan.maturity_ts,
+        });
 
         Ok(())
     }
 
-    /// Any of the three roles may deposit SPL tokens into the vault ATA.
-    pub fn deposit_collateral(ctx: Context<DepositCollateral>, amount: u64) -> Result<()> {
+    /// Any of the three roles may deposit a mint listed in the eligibility schedule into its
+    /// vault ATA. With Pyth enabled the deposit must also respect the schedule's oracle
+    /// confidence and concentration limits (feeds of every funded basket asset passed as
+    /// remaining accounts). A transfer-hook mint's extra accounts join the remaining accounts.
+    pub fn deposit_collateral<'info>(
+        ctx: Context<'_, '_, '_, 'info, DepositCollateral<'info>>,
+        amount: u64,
+    ) -> Result<()> {
         require!(amount > 0, ErrorCode::InvalidAmount);
         let state = &mut ctx.accounts.vault_state;
         require!(!state.is_frozen, ErrorCode::Paused);
+        let now = Clock::get()?.unix_timestamp;
+        state.loan.accrue(now)?;
 
-        // Prevent depositing while approvals exist (clear flow ambiguity)
-        require!(state.approvals_bitmap == 0, ErrorCode::PendingReleaseFlow);
+        // Prevent depositing while a release proposal is open (clear flow ambiguity);
+        // margin-call top-ups are always allowed so an open proposal can't block a cure
+        require!(
+            state.open_release_proposals == 0 || state.status == VaultStatus::MarginCall,
+            ErrorCode::PendingReleaseFlow
+        );
 
         // Depositor must be a recognized role
         require!(
@@ -117,92 +310,401 @@ pub mod tri_party_vault {
             ErrorCode::Unauthorized
         );
 
-        // Transfer from depositor_ata -> vault_ata with depositor as authority
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.depositor_ata.to_account_info(),
-            to: ctx.accounts.vault_ata.to_account_info(),
-            authority: ctx.accounts.depositor.to_account_info(),
-        };
-        let cpi_ctx =
-            CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
-        token_i::transfer(cpi_ctx, amount)?;
+        let mint = ctx.accounts.mint_account.key();
+        let rule = *ctx
+            .accounts
+            .eligibility_schedule
+            .entry(mint)
+            .ok_or(ErrorCode::MintNotEligible)?;
+
+        // Eligible mints join the basket on their first deposit
+        let idx = basket_slot(state, &rule, &ctx.accounts.mint_account)?;
+
+        // Transfer from depositor_ata -> vault_ata with depositor as authority; a transfer-hook
+        // mint's extra accounts are resolved from the remaining accounts
+        let balance_before = ctx.accounts.vault_ata.amount;
+        spl_token_2022::onchain::invoke_transfer_checked(
+            ctx.accounts.token_program.key,
+            ctx.accounts.depositor_ata.to_account_info(),
+            ctx.accounts.mint_account.to_account_info(),
+            ctx.accounts.vault_ata.to_account_info(),
+            ctx.accounts.depositor.to_account_info(),
+            ctx.remaining_accounts,
+            amount,
+            ctx.accounts.mint_account.decimals,
+            &[],
+        )?;
+
+        // Book what arrived: a Token-2022 transfer fee is withheld from the vault ATA's credit
+        ctx.accounts.vault_ata.reload()?;
+        let received = ctx
+            .accounts
+            .vault_ata
+            .amount
+            .checked_sub(balance_before)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let fee = amount.checked_sub(received).ok_or(ErrorCode::MathOverflow)?;
+        require!(received > 0, ErrorCode::InvalidAmount);
 
         // Update locked amount (checked math)
-        state.amount_locked = state
+        let asset = &mut state.basket[idx];
+        asset.amount_locked = asset
             .amount_locked
-            .checked_add(amount)
+            .checked_add(received)
             .ok_or(ErrorCode::MathOverflow)?;
+        let new_total = asset.amount_locked;
+
+        // Schedule limits need prices (skipped in token-denominated fallback mode)
+        if state.price_config.enabled {
+            let mints = [ctx.accounts.mint_account.to_account_info()];
+            let prices =
+                load_basket_prices(state, state.key(), &mints, ctx.remaining_accounts, now)?;
+            let px = prices[idx].ok_or(ErrorCode::PriceAccountInvalid)?;
+            check_confidence(&px, rule.max_conf_bps)?;
+            check_concentration(state, &prices, idx, received, rule.max_concentration_bps)?;
+        }
 
         emit!(CollateralDeposited {
+            mint,
+            amount: received,
+            fee,
+            new_total
+        });
+        Ok(())
+    }
+
+    /// Atomically swap basket collateral: the borrower deposits `amount_in` of an eligible mint
+    /// and takes back `amount_out` of a basket mint. The basket's lending value (price - conf,
+    /// after haircuts) may not fall. The custodian signs off; the lender must co-sign when the
+    /// incoming mint breaks its schedule confidence or concentration limit. Feeds of every
+    /// basket asset funded before or after the swap are passed as remaining accounts, plus
+    /// either mint's transfer-hook extra accounts. Refused while a release proposal is open.
+    pub fn substitute_collateral<'info>(
+        ctx: Context<'_, '_, '_, 'info, SubstituteCollateral<'info>>,
+        amount_in: u64,
+        amount_out: u64,
+    ) -> Result<()> {
+        require!(amount_in > 0 && amount_out > 0, ErrorCode::InvalidAmount);
+        let mint_in = ctx.accounts.mint_in.key();
+        let mint_out = ctx.accounts.mint_out.key();
+        require!(mint_in != mint_out, ErrorCode::InvalidAmount);
+
+        let state = &mut ctx.accounts.vault_state;
+        require!(!state.is_frozen, ErrorCode::Paused);
+        require!(state.status != VaultStatus::Liquidating, ErrorCode::AuctionActive);
+        require!(state.price_config.enabled, ErrorCode::PriceChecksDisabled);
+        // Collateral under an open release proposal may not be swapped out from under it
+        require!(state.open_release_proposals == 0, ErrorCode::PendingReleaseFlow);
+        require_keys_eq!(ctx.accounts.custodian.key(), state.custodian, ErrorCode::Unauthorized);
+        let now = Clock::get()?.unix_timestamp;
+        state.loan.accrue(now)?;
+
+        let rule = *ctx
+            .accounts
+            .eligibility_schedule
+            .entry(mint_in)
+            .ok_or(ErrorCode::MintNotEligible)?;
+        let idx_out = asset_index(state, mint_out)?;
+        require!(
+            amount_out <= state.basket[idx_out].amount_locked,
+            ErrorCode::AmountExceedsLocked
+        );
+
+        // Extra ownership pinning
+        require_keys_eq!(
+            ctx.accounts.vault_ata_in.owner,
+            ctx.accounts.vault_authority.key(),
+            ErrorCode::Unauthorized
+        );
+        require_keys_eq!(
+            ctx.accounts.vault_ata_out.owner,
+            ctx.accounts.vault_authority.key(),
+            ErrorCode::Unauthorized
+        );
+
+        // Value before, then move and book both legs and value again
+        let mints = [
+            ctx.accounts.mint_in.to_account_info(),
+            ctx.accounts.mint_out.to_account_info(),
+        ];
+        let prices_before =
+            load_valuation_prices(state, state.key(), &mints, ctx.remaining_accounts, now)?;
+        if let Some(trip) = check_withdrawal_prices(state, &prices_before, now)? {
+            trip_circuit_breaker(state, trip, now);
+            return Ok(());
+        }
+        let value_before = basket_value_quote_1e6(state, &prices_before)?;
+
+        let idx_in = basket_slot(state, &rule, &ctx.accounts.mint_in)?;
+
+        // Incoming leg: borrower_ata_in -> vault_ata_in with borrower as authority
+        let balance_before_in = ctx.accounts.vault_ata_in.amount;
+        spl_token_2022::onchain::invoke_transfer_checked(
+            ctx.accounts.token_program_in.key,
+            ctx.accounts.borrower_ata_in.to_account_info(),
+            ctx.accounts.mint_in.to_account_info(),
+            ctx.accounts.vault_ata_in.to_account_info(),
+            ctx.accounts.borrower.to_account_info(),
+            ctx.remaining_accounts,
+            amount_in,
+            ctx.accounts.mint_in.decimals,
+            &[],
+        )?;
+
+        // ---- PDA signer seeds (stable lifetime binding) ----
+        let state_key = state.key();
+        let signer_seed_slice: [&[u8]; 3] = [
+            SEED_AUTH,
+            state_key.as_ref(),
+            &[state.vault_authority_bump],
+        ];
+        let signer: &[&[u8]] = &signer_seed_slice;
+        let signer_arr: &[&[&[u8]]] = &[signer];
+        // ----------------------------------------------------
+
+        // Outgoing leg: vault_ata_out -> borrower_ata_out with the PDA signer
+        spl_token_2022::onchain::invoke_transfer_checked(
+            ctx.accounts.token_program_out.key,
+            ctx.accounts.vault_ata_out.to_account_info(),
+            ctx.accounts.mint_out.to_account_info(),
+            ctx.accounts.borrower_ata_out.to_account_info(),
+            ctx.accounts.vault_authority.to_account_info(),
+            ctx.remaining_accounts,
+            amount_out,
+            ctx.accounts.mint_out.decimals,
+            signer_arr,
+        )?;
+
+        // Book both legs once the tokens have moved; the incoming leg at what vault_ata_in
+        // actually received (net of any Token-2022 transfer fee)
+        ctx.accounts.vault_ata_in.reload()?;
+        let received_in = ctx
+            .accounts
+            .vault_ata_in
+            .amount
+            .checked_sub(balance_before_in)
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(received_in > 0, ErrorCode::InvalidAmount);
+        let asset_in = &mut state.basket[idx_in];
+        asset_in.amount_locked = asset_in
+            .amount_locked
+            .checked_add(received_in)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let asset_out = &mut state.basket[idx_out];
+        asset_out.amount_locked = asset_out
+            .amount_locked
+            .checked_sub(amount_out)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let prices_after =
+            load_valuation_prices(state, state.key(), &mints, ctx.remaining_accounts, now)?;
+        let value_after = basket_value_quote_1e6(state, &prices_after)?;
+        require!(value_after >= value_before, ErrorCode::SubstitutionValueShortfall);
+
+        // Custodian alone suffices inside the schedule; otherwise the lender must co-sign
+        let px_in = prices_after[idx_in].ok_or(ErrorCode::PriceAccountInvalid)?;
+        let within_schedule = check_confidence(&px_in, rule.max_conf_bps).is_ok()
+            && concentration_within(state, &prices_after, idx_in, rule.max_concentration_bps)?;
+        let lender_signed = ctx.accounts.lender.is_some();
+        if !within_schedule {
+            require!(lender_signed, ErrorCode::LenderSignOffRequired);
+        }
+
+        emit!(CollateralSubstituted {
+            mint_in,
+            amount_in: received_in,
+            mint_out,
+            amount_out,
+            value_before_quote_1e6: to_u64(value_before)?,
+            value_after_quote_1e6: to_u64(value_after)?,
+            lender_signed,
+        });
+        Ok(())
+    }
+
+    /// Open a release proposal binding amount, recipient owner, mint and expiry; the proposer's
+    /// approval is recorded immediately. With `net_of_fee`, `amount` is what the recipient must
+    /// receive and the release is grossed up for the mint's transfer fee at execution.
+    pub fn propose_release(
+        ctx: Context<ProposeRelease>,
+        role: u8,
+        mint: Pubkey,
+        amount: u64,
+        net_of_fee: bool,
+        recipient: Pubkey,
+        expires_at: i64,
+    ) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        let state = &mut ctx.accounts.vault_state;
+        require!(!state.is_frozen, ErrorCode::Paused);
+
+        // Auth: signer must match role
+        let expected = role_key(state, role)?;
+        require_keys_eq!(ctx.accounts.proposer.key(), expected, ErrorCode::Unauthorized);
+
+        require!(recipient != Pubkey::default(), ErrorCode::Unauthorized);
+        let idx = asset_index(state, mint)?;
+        require!(
+            amount <= state.basket[idx].amount_locked,
+            ErrorCode::AmountExceedsLocked
+        );
+
+        let now = Clock::get()?.unix_timestamp;
+        check_new_expiry(now, expires_at)?;
+        state.loan.accrue(now)?;
+
+        let nonce = state.release_nonce;
+        let proposal = &mut ctx.accounts.release_proposal;
+        proposal.vault = state.key();
+        proposal.nonce = nonce;
+        proposal.proposer = ctx.accounts.proposer.key();
+        proposal.amount = amount;
+        proposal.net_of_fee = net_of_fee;
+        proposal.recipient = recipient;
+        proposal.mint = mint;
+        proposal.expires_at = expires_at;
+        proposal.roles_epoch = state.roles_epoch;
+        proposal.approvals_bitmap = 0;
+        set_bit(&mut proposal.approvals_bitmap, role);
+        proposal.bump = ctx.bumps.release_proposal;
+
+        state.release_nonce = nonce.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+        state.open_release_proposals = state
+            .open_release_proposals
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(ReleaseProposed {
+            proposal: proposal.key(),
+            nonce,
+            proposer: proposal.proposer,
             amount,
-            new_total: state.amount_locked
+            net_of_fee,
+            recipient,
+            mint: proposal.mint,
+            expires_at,
         });
         Ok(())
     }
 
-    /// Role-gated approval; idempotent bit set for (0=custodian,1=borrower,2=lender).
+    /// Role-gated approval of a release proposal; idempotent bit set for (0=custodian,1=borrower,2=lender).
     pub fn approve_release(ctx: Context<ApproveRelease>, role: u8) -> Result<()> {
-        let state = &mut ctx.accounts.vault_state;
+        let state = &ctx.accounts.vault_state;
         // Auth: signer must match role
-        require!(role <= 2, ErrorCode::InvalidRole);
-        let signer = ctx.accounts.role_signer.key();
-        let expected = match role {
-            0 => state.custodian,
-            1 => state.borrower,
-            _ => state.lender,
-        };
-        require_keys_eq!(signer, expected, ErrorCode::Unauthorized);
+        let expected = role_key(state, role)?;
+        require_keys_eq!(ctx.accounts.role_signer.key(), expected, ErrorCode::Unauthorized);
+
+        let proposal = &mut ctx.accounts.release_proposal;
+        require!(proposal.roles_epoch == state.roles_epoch, ErrorCode::StaleProposal);
+        let now = Clock::get()?.unix_timestamp;
+        require!(now < proposal.expires_at, ErrorCode::ProposalExpired);
 
         // Idempotent set
-        if !has_bit(state.approvals_bitmap, role) {
-            set_bit(&mut state.approvals_bitmap, role);
+        if !has_bit(proposal.approvals_bitmap, role) {
+            set_bit(&mut proposal.approvals_bitmap, role);
         }
         emit!(ReleaseApproved {
+            proposal: proposal.key(),
             by_role: role,
-            approvals_bitmap: state.approvals_bitmap
+            approvals_bitmap: proposal.approvals_bitmap
         });
         Ok(())
     }
 
-    /// Allow a role to revoke its approval before release.
+    /// Allow a role to revoke its approval of a proposal before release.
     pub fn revoke_approval(ctx: Context<ApproveRelease>, role: u8) -> Result<()> {
-        let state = &mut ctx.accounts.vault_state;
-        require!(role <= 2, ErrorCode::InvalidRole);
-        let signer = ctx.accounts.role_signer.key();
-        let expected = [state.custodian, state.borrower, state.lender][role as usize];
-        require_keys_eq!(signer, expected, ErrorCode::Unauthorized);
-        if has_bit(state.approvals_bitmap, role) {
-            clear_bit(&mut state.approvals_bitmap, role);
+        let state = &ctx.accounts.vault_state;
+        let expected = role_key(state, role)?;
+        require_keys_eq!(ctx.accounts.role_signer.key(), expected, ErrorCode::Unauthorized);
+
+        let proposal = &mut ctx.accounts.release_proposal;
+        if has_bit(proposal.approvals_bitmap, role) {
+            clear_bit(&mut proposal.approvals_bitmap, role);
             emit!(ReleaseApproved {
+                proposal: proposal.key(),
                 by_role: role,
-                approvals_bitmap: state.approvals_bitmap
+                approvals_bitmap: proposal.approvals_bitmap
             });
         }
         Ok(())
     }
 
-    /// Release to a recipient ATA when approvals >= threshold; USD caps/LTV enforced if Pyth is enabled.
-    pub fn release_collateral(ctx: Context<ReleaseCollateral>, amount: u64) -> Result<()> {
-        require!(amount > 0, ErrorCode::InvalidAmount);
+    /// Close a release proposal without executing it. The proposer may cancel at any time;
+    /// anyone may clean up once the proposal has expired or gone stale after a role
+    /// rotation, so a dead proposal cannot keep deposits and substitutions blocked.
+    /// Rent goes back to the proposer.
+    pub fn cancel_release(ctx: Context<CancelRelease>) -> Result<()> {
+        let state = &mut ctx.accounts.vault_state;
+        let proposal = &ctx.accounts.release_proposal;
+        let signer = ctx.accounts.role_signer.key();
+
+        let now = Clock::get()?.unix_timestamp;
+        let dead = now >= proposal.expires_at || proposal.roles_epoch != state.roles_epoch;
+        require!(signer == proposal.proposer || dead, ErrorCode::Unauthorized);
+        state.loan.accrue(now)?;
+
+        state.open_release_proposals = state
+            .open_release_proposals
+            .checked_sub(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(ReleaseCancelled {
+            proposal: proposal.key(),
+            nonce: proposal.nonce,
+            cancelled_by: signer,
+        });
+        Ok(())
+    }
+
+    /// Execute exactly what a release proposal approved once the release quorum is met;
+    /// quote-currency caps/LTV enforced over the whole basket if Pyth is enabled (feeds of every funded
+    /// basket asset passed as remaining accounts, plus a transfer-hook mint's extra accounts).
+    /// The proposal is closed on success; if the price-move circuit breaker trips, the vault
+    /// is paused and the proposal stays open.
+    pub fn release_collateral<'info>(
+        ctx: Context<'_, '_, '_, 'info, ReleaseCollateral<'info>>,
+    ) -> Result<()> {
+        let proposal = &ctx.accounts.release_proposal;
+        let proposal_key = proposal.key();
 
         let state = &mut ctx.accounts.vault_state;
         require!(!state.is_frozen, ErrorCode::Paused);
+        require!(state.status != VaultStatus::Liquidating, ErrorCode::AuctionActive);
+
+        // Executor must be one of the roles or the configured executor key
+        let executed_by = ctx.accounts.executor.key();
+        require!(
+            is_role(state, executed_by)
+                || (state.executor != Pubkey::default() && executed_by == state.executor),
+            ErrorCode::UnauthorizedExecutor
+        );
+
+        // Must meet the release quorum on this proposal, and it must still be live
+        require!(proposal.roles_epoch == state.roles_epoch, ErrorCode::StaleProposal);
+        require_quorum(state, QuorumAction::Release, proposal.approvals_bitmap)?;
+        let clock = Clock::get()?;
+        let now = clock.unix_timestamp;
+        require!(now < proposal.expires_at, ErrorCode::ProposalExpired);
+        state.loan.accrue(now)?;
 
-        // Must have >= threshold approvals
-        let approvals = bitcount(state.approvals_bitmap);
-        require!(approvals >= state.threshold as u32, ErrorCode::NotEnoughApprovals);
+        // Gross amount leaving the vault and the transfer fee withheld from the recipient
+        let mint_info = ctx.accounts.mint_account.to_account_info();
+        let amount = if proposal.net_of_fee {
+            gross_up_for_fee(&mint_info, proposal.amount, clock.epoch)?
+        } else {
+            proposal.amount
+        };
+        let fee = transfer_fee(&mint_info, amount, clock.epoch)?;
 
         // Bounds (token-denominated basic checks)
-        require!(amount <= state.amount_locked, ErrorCode::AmountExceedsLocked);
-        require!(ctx.accounts.recipient.key() != Pubkey::default(), ErrorCode::Unauthorized);
+        let idx = asset_index(state, proposal.mint)?;
+        require!(
+            amount <= state.basket[idx].amount_locked,
+            ErrorCode::AmountExceedsLocked
+        );
 
-        // Daily cap window reset (both token and USD counters)
-        let now = Clock::get()?.unix_timestamp;
-        if now - state.last_cap_reset_ts >= 86_400 {
-            state.last_cap_reset_ts = now;
-            state.released_today = 0;
-            state.released_today_usd_1e6 = 0;
-        }
+        // Daily cap window reset (both token and quote counters)
+        roll_cap_window(state, now);
 
         // Token-denominated fallback caps when Pyth disabled
         if !state.price_config.enabled {
@@ -215,105 +717,37 @@ pub mod tri_party_vault {
             state.released_today = new_today;
         }
 
-        // USD-denominated caps & LTV when Pyth enabled
+        // Quote-denominated caps & LTV when Pyth enabled
         if state.price_config.enabled {
-            let price_acc_key = ctx
-                .accounts
-                .pyth_price_acc
-                .as_ref()
-                .map(|a| a.key())
-                .unwrap_or(Pubkey::default());
-            require_keys_eq!(price_acc_key, state.price_config.sol_usdc_price_feed, ErrorCode::Unauthorized);
-
-            let price_acc_info = ctx
-                .accounts
-                .pyth_price_acc
-                .as_ref()
-                .ok_or(ErrorCode::PriceAccountInvalid)?
-                .to_account_info();
-
-            let feed = load_price_feed_from_account_info(&price_acc_info)
-                .map_err(|_| ErrorCode::PriceAccountInvalid)?;
-
-            // ---- FIX: pass u64 staleness window to Pyth (0.8.0) ----
-            require!(state.price_config.max_price_staleness_secs >= 0, ErrorCode::InvalidRiskParams);
-            let staleness: u64 = state
-                .price_config
-                .max_price_staleness_secs
-                .try_into()
-                .map_err(|_| ErrorCode::InvalidRiskParams)?;
-            let px = feed
-                .get_price_no_older_than(now, staleness)
-                .ok_or(ErrorCode::PriceStale)?;
-            // --------------------------------------------------------
-
-            // Conservative price: price - conf
-            let p_conservative = (px.price as i128)
-                .checked_sub(px.conf as i128)
-                .ok_or(ErrorCode::MathOverflow)?;
-            if p_conservative <= 0 {
-                return err!(ErrorCode::PriceNonPositive);
+            let mints = [mint_info.clone()];
+            let prices =
+                load_valuation_prices(state, state.key(), &mints, ctx.remaining_accounts, now)?;
+            // Breaker trip: pause and keep the proposal open instead of releasing
+            if let Some(trip) = check_withdrawal_prices(state, &prices, now)? {
+                trip_circuit_breaker(state, trip, now);
+                return Ok(());
             }
+            let px = prices[idx].ok_or(ErrorCode::PriceAccountInvalid)?;
+            let p_i = conservative_price(&px)?;
+            let asset = &state.basket[idx];
 
-            // Convert amounts to USD 1e6 (micro-USDC) using integer math:
-            // usd_1e6 = amount * (price - conf) * 10^6 / (10^mint_decimals * 10^(-expo))
-            let denom_exp: i32 = state.mint_decimals as i32 - px.expo; // = mint_dec + (-expo)
-            let denom = ten_pow_u128(denom_exp).ok_or(ErrorCode::MathOverflow)?;
-            let amount_i = amount as u128;
-            let p_i = p_conservative as u128;
-
-            let release_usd_num = amount_i
-                .checked_mul(p_i)
-                .and_then(|v| v.checked_mul(1_000_000u128))
-                .ok_or(ErrorCode::MathOverflow)?;
-            let release_usd_1e6 = release_usd_num
-                .checked_div(denom)
-                .ok_or(ErrorCode::MathOverflow)?; // floor conservative
-
-            let total_usd_num = (state.amount_locked as u128)
-                .checked_mul(p_i)
-                .and_then(|v| v.checked_mul(1_000_000u128))
-                .ok_or(ErrorCode::MathOverflow)?;
-            let total_usd_1e6 = total_usd_num
-                .checked_div(denom)
-                .ok_or(ErrorCode::MathOverflow)?;
-
-            // Per-tx USD cap
-            require!(
-                release_usd_1e6 <= state.price_config.max_single_release_usd_1e6 as u128,
-                ErrorCode::UsdCapExceeded
-            );
+            // Caps measure market value leaving; LTV compares haircut (lending) values
+            let release_quote_1e6 = quote_value_1e6(amount, asset.decimals, p_i, px.expo)?;
+            let release_lending_quote_1e6 = apply_haircut(release_quote_1e6, asset.haircut_bps)?;
+            let total_quote_1e6 = basket_value_quote_1e6(state, &prices)?;
 
-            // Daily USD cap
-            let new_today_usd = (state.released_today_usd_1e6 as u128)
-                .checked_add(release_usd_1e6)
-                .ok_or(ErrorCode::MathOverflow)?;
-            require!(
-                new_today_usd <= state.price_config.daily_cap_usd_1e6 as u128,
-                ErrorCode::UsdCapExceeded
-            );
+            // Per-tx and daily quote caps (tracked in `released_today_quote_1e6`)
+            charge_quote_caps(state, release_quote_1e6)?;
 
-            // LTV guard (interpreted as: post-release collateral must be >= (1 - LTV) * pre-release)
-            let remaining_usd_1e6 = total_usd_1e6
-                .checked_sub(release_usd_1e6)
+            // LTV guard (interpreted as: post-release basket must be >= (1 - LTV) * pre-release)
+            let remaining_quote_1e6 = total_quote_1e6
+                .checked_sub(release_lending_quote_1e6)
                 .ok_or(ErrorCode::MathOverflow)?;
-            let min_remaining_usd_1e6 = total_usd_1e6
+            let min_remaining_quote_1e6 = total_quote_1e6
                 .checked_mul((10_000 - state.price_config.max_ltv_bps) as u128)
                 .and_then(|v| v.checked_div(10_000))
                 .ok_or(ErrorCode::MathOverflow)?;
-            require!(remaining_usd_1e6 >= min_remaining_usd_1e6, ErrorCode::LtvBreach);
-
-            // Track USD daily
-            state.released_today_usd_1e6 = new_today_usd as u64;
-
-            // Emit price diagnostic
-            emit!(PriceUsed {
-                feed: state.price_config.sol_usdc_price_feed,
-                price: px.price,
-                conf: px.conf,
-                expo: px.expo,
-                publish_time: px.publish_time,
-            });
+            require!(remaining_quote_1e6 >= min_remaining_quote_1e6, ErrorCode::LtvBreach);
         }
 
         // Extra ownership pinning
@@ -334,514 +768,5914 @@ pub mod tri_party_vault {
         let signer_arr: &[&[&[u8]]] = &[signer];
         // ----------------------------------------------------
 
-        // CPI transfer vault_ata -> recipient_ata
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.vault_ata.to_account_info(),
-            to: ctx.accounts.recipient_ata.to_account_info(),
-            authority: ctx.accounts.vault_authority.to_account_info(),
-        };
-        let cpi_ctx = CpiContext::new_with_signer(
-            ctx.accounts.token_program.to_account_info(),
-            cpi_accounts,
+        // CPI transfer vault_ata -> recipient_ata; a transfer-hook mint's extra accounts are
+        // resolved from the remaining accounts
+        spl_token_2022::onchain::invoke_transfer_checked(
+            ctx.accounts.token_program.key,
+            ctx.accounts.vault_ata.to_account_info(),
+            mint_info,
+            ctx.accounts.recipient_ata.to_account_info(),
+            ctx.accounts.vault_authority.to_account_info(),
+            ctx.remaining_accounts,
+            amount,
+            ctx.accounts.mint_account.decimals,
             signer_arr,
-        );
-        token_i::transfer(cpi_ctx, amount)?;
+        )?;
 
-        // Update accounting & reset approvals
-        state.amount_locked = state
+        // Update accounting; the executed proposal is closed below
+        let asset = &mut state.basket[idx];
+        asset.amount_locked = asset
             .amount_locked
             .checked_sub(amount)
             .ok_or(ErrorCode::MathOverflow)?;
-        state.approvals_bitmap = 0;
+        let remaining = asset.amount_locked;
+        state.open_release_proposals = state
+            .open_release_proposals
+            .checked_sub(1)
+            .ok_or(ErrorCode::MathOverflow)?;
 
         emit!(CollateralReleased {
+            proposal: proposal_key,
             recipient: ctx.accounts.recipient.key(),
+            mint: ctx.accounts.mint_account.key(),
             amount,
-            remaining: state.amount_locked,
-            approvals_after: state.approvals_bitmap,
+            fee,
+            remaining,
+            executed_by,
         });
 
-        Ok(())
+        ctx.accounts
+            .release_proposal
+            .close(ctx.accounts.proposer.to_account_info())
     }
 
-    /// Pause guard: only custodian can pause.
-    pub fn pause(ctx: Context<Pause>) -> Result<()> {
+    /// Borrower-only: withdraw collateral beyond what the loan and maintenance margin need,
+    /// without a release proposal. Free margin = basket lending value (price - conf, after
+    /// haircuts) - debt * maintenance ratio; the withdrawal's lending value must fit in it.
+    /// Daily/per-tx quote caps and the pause flag still apply. Feeds of every funded basket
+    /// asset are passed as remaining accounts, plus a transfer-hook mint's extra accounts.
+    pub fn withdraw_excess<'info>(
+        ctx: Context<'_, '_, '_, 'info, WithdrawExcess<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
         let state = &mut ctx.accounts.vault_state;
-        require_keys_eq!(
-            ctx.accounts.custodian.key(),
-            state.custodian,
-            ErrorCode::Unauthorized
+        require!(!state.is_frozen, ErrorCode::Paused);
+        require!(state.status == VaultStatus::Active, ErrorCode::NoExcessCollateral);
+        require!(state.price_config.enabled, ErrorCode::PriceChecksDisabled);
+
+        let now = Clock::get()?.unix_timestamp;
+        state.loan.accrue(now)?;
+
+        let idx = asset_index(state, ctx.accounts.mint_account.key())?;
+        require!(
+            amount <= state.basket[idx].amount_locked,
+            ErrorCode::AmountExceedsLocked
         );
-        state.is_frozen = true;
-        emit!(Paused {});
-        emit!(StateSignal {
-            paused: true,
-            approvals_bitmap: state.approvals_bitmap,
-            amount_locked: state.amount_locked
-        });
-        Ok(())
-    }
 
-    /// Unpause guard: only custodian can unpause.
-    pub fn unpause(ctx: Context<Pause>) -> Result<()> {
-        let state = &mut ctx.accounts.vault_state;
+        let mints = [ctx.accounts.mint_account.to_account_info()];
+        let prices =
+            load_valuation_prices(state, state.key(), &mints, ctx.remaining_accounts, now)?;
+        if let Some(trip) = check_withdrawal_prices(state, &prices, now)? {
+            trip_circuit_breaker(state, trip, now);
+            return Ok(());
+        }
+        let px = prices[idx].ok_or(ErrorCode::PriceAccountInvalid)?;
+        let asset = &state.basket[idx];
+        let withdraw_quote_1e6 =
+            quote_value_1e6(amount, asset.decimals, conservative_price(&px)?, px.expo)?;
+        let withdraw_lending_quote_1e6 = apply_haircut(withdraw_quote_1e6, asset.haircut_bps)?;
+
+        // Free margin over debt * maintenance ratio (rounded up against the borrower)
+        let collateral_quote_1e6 = basket_value_quote_1e6(state, &prices)?;
+        let debt_quote_1e6 = debt_quote_1e6(state.loan.outstanding()?, state.loan.debt_decimals)?;
+        let required_quote_1e6 = debt_quote_1e6
+            .checked_mul(state.price_config.maintenance_ratio_bps as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            .div_ceil(10_000);
+        let free_margin_quote_1e6 = collateral_quote_1e6.saturating_sub(required_quote_1e6);
+        require!(
+            withdraw_lending_quote_1e6 <= free_margin_quote_1e6,
+            ErrorCode::NoExcessCollateral
+        );
+
+        roll_cap_window(state, now);
+        charge_quote_caps(state, withdraw_quote_1e6)?;
+
+        // Extra ownership pinning
         require_keys_eq!(
-            ctx.accounts.custodian.key(),
-            state.custodian,
+            ctx.accounts.vault_ata.owner,
+            ctx.accounts.vault_authority.key(),
             ErrorCode::Unauthorized
         );
-        state.is_frozen = false;
-        emit!(Unpaused {});
-        emit!(StateSignal {
-            paused: false,
-            approvals_bitmap: state.approvals_bitmap,
-            amount_locked: state.amount_locked
+
+        // ---- PDA signer seeds (stable lifetime binding) ----
+        let state_key = state.key();
+        let signer_seed_slice: [&[u8]; 3] = [
+            SEED_AUTH,
+            state_key.as_ref(),
+            &[state.vault_authority_bump],
+        ];
+        let signer: &[&[u8]] = &signer_seed_slice;
+        let signer_arr: &[&[&[u8]]] = &[signer];
+        // ----------------------------------------------------
+
+        // CPI transfer vault_ata -> borrower_ata
+        spl_token_2022::onchain::invoke_transfer_checked(
+            ctx.accounts.token_program.key,
+            ctx.accounts.vault_ata.to_account_info(),
+            ctx.accounts.mint_account.to_account_info(),
+            ctx.accounts.borrower_ata.to_account_info(),
+            ctx.accounts.vault_authority.to_account_info(),
+            ctx.remaining_accounts,
+            amount,
+            ctx.accounts.mint_account.decimals,
+            signer_arr,
+        )?;
+
+        let asset = &mut state.basket[idx];
+        asset.amount_locked = asset
+            .amount_locked
+            .checked_sub(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let remaining = asset.amount_locked;
+
+        emit!(ExcessWithdrawn {
+            mint: ctx.accounts.mint_account.key(),
+            amount,
+            value_quote_1e6: to_u64(withdraw_quote_1e6)?,
+            free_margin_quote_1e6: to_u64(free_margin_quote_1e6)?,
+            remaining,
         });
         Ok(())
     }
 
-    /// Clear approvals bitmap (custodian-only).
-    pub fn reset_approvals(ctx: Context<Pause>) -> Result<()> {
+    /// Delivery-versus-payment unwind: the borrower pays `repay_amount` of the debt mint to
+    /// the lender's ATA and, in the same instruction, receives the proportional share of the
+    /// passed basket mint back from the vault. What the lender's ATA received (net of any
+    /// Token-2022 transfer fee) is what gets booked, interest first, then principal.
+    /// Transfer-hook extra accounts of either mint are passed as remaining accounts.
+    pub fn repay_and_release<'info>(
+        ctx: Context<'_, '_, '_, 'info, RepayAndRelease<'info>>,
+        repay_amount: u64,
+    ) -> Result<()> {
+        require!(repay_amount > 0, ErrorCode::InvalidAmount);
         let state = &mut ctx.accounts.vault_state;
+        require!(!state.is_frozen, ErrorCode::Paused);
+        require!(state.status != VaultStatus::Liquidating, ErrorCode::AuctionActive);
+
+        let now = Clock::get()?.unix_timestamp;
+        state.loan.accrue(now)?;
+        let outstanding = state.loan.outstanding()?;
+        let idx = asset_index(state, ctx.accounts.mint_account.key())?;
+
+        // Extra ownership pinning
         require_keys_eq!(
-            ctx.accounts.custodian.key(),
-            state.custodian,
+            ctx.accounts.vault_ata.owner,
+            ctx.accounts.vault_authority.key(),
             ErrorCode::Unauthorized
         );
-        state.approvals_bitmap = 0;
-        Ok(())
-    }
 
-    /// Governance-like role rotation; requires >= threshold approvals.
-    pub fn rotate_role(ctx: Context<RotateRole>, role: u8, new_key: Pubkey) -> Result<()> {
+        // Payment leg: borrower_debt_ata -> lender_debt_ata with borrower as authority
+        let lender_before = ctx.accounts.lender_debt_ata.amount;
+        spl_token_2022::onchain::invoke_transfer_checked(
+            ctx.accounts.debt_token_program.key,
+            ctx.accounts.borrower_debt_ata.to_account_info(),
+            ctx.accounts.debt_mint_account.to_account_info(),
+            ctx.accounts.lender_debt_ata.to_account_info(),
+            ctx.accounts.borrower.to_account_info(),
+            ctx.remaining_accounts,
+            repay_amount,
+            ctx.accounts.debt_mint_account.decimals,
+            &[],
+        )?;
+
+        // Book what the lender received: a Token-2022 transfer fee is withheld from the credit
+        ctx.accounts.lender_debt_ata.reload()?;
+        let received = ctx
+            .accounts
+            .lender_debt_ata
+            .amount
+            .checked_sub(lender_before)
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(received > 0, ErrorCode::InvalidAmount);
+        require!(received <= outstanding, ErrorCode::RepayExceedsDebt);
+
+        // Proportional collateral (floor); a full repayment releases all of this mint
         let state = &mut ctx.accounts.vault_state;
-        require!(
-            bitcount(state.approvals_bitmap) >= state.threshold as u32,
-            ErrorCode::NotEnoughApprovals
-        );
-        match role {
-            0 => state.custodian = new_key,
-            1 => state.borrower = new_key,
-            2 => state.lender = new_key,
-            _ => return err!(ErrorCode::InvalidRole),
+        let locked = state.basket[idx].amount_locked;
+        let collateral_out: u64 = if received == outstanding {
+            locked
+        } else {
+            (locked as u128)
+                .checked_mul(received as u128)
+                .and_then(|v| v.checked_div(outstanding as u128))
+                .ok_or(ErrorCode::MathOverflow)?
+                .try_into()
+                .map_err(|_| ErrorCode::MathOverflow)?
+        };
+
+        // Delivery leg: vault_ata -> borrower_collateral_ata with the PDA signer
+        if collateral_out > 0 {
+            let state_key = state.key();
+            let signer_seed_slice: [&[u8]; 3] = [
+                SEED_AUTH,
+                state_key.as_ref(),
+                &[state.vault_authority_bump],
+            ];
+            let signer: &[&[u8]] = &signer_seed_slice;
+            let signer_arr: &[&[&[u8]]] = &[signer];
+
+            spl_token_2022::onchain::invoke_transfer_checked(
+                ctx.accounts.token_program.key,
+                ctx.accounts.vault_ata.to_account_info(),
+                ctx.accounts.mint_account.to_account_info(),
+                ctx.accounts.borrower_collateral_ata.to_account_info(),
+                ctx.accounts.vault_authority.to_account_info(),
+                ctx.remaining_accounts,
+                collateral_out,
+                ctx.accounts.mint_account.decimals,
+                signer_arr,
+            )?;
+        }
+
+        // Book the repayment: interest first, then principal
+        let (interest_paid, principal_paid) = state.loan.apply_payment(received)?;
+        // Clearing the debt ends a margin call or default
+        if state.loan.outstanding()? == 0 {
+            state.status = VaultStatus::Active;
+            state.margin_call_deadline = 0;
         }
-        // Clear approvals after governance change
-        state.approvals_bitmap = 0;
+        let asset = &mut state.basket[idx];
+        asset.amount_locked = asset
+            .amount_locked
+            .checked_sub(collateral_out)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(LoanRepaid {
+            repay_amount,
+            received,
+            interest_paid,
+            principal_paid,
+            collateral_mint: asset.mint,
+            collateral_released: collateral_out,
+            remaining_collateral: asset.amount_locked,
+            outstanding_after: state.loan.outstanding()?,
+        });
         Ok(())
     }
 
-    /// Admin: set Pyth price feed and on/off.
-    pub fn set_price_feed(ctx: Context<Admin>, feed: Pubkey, enabled: bool) -> Result<()> {
-        let s = &mut ctx.accounts.vault_state;
-        require_keys_eq!(ctx.accounts.custodian.key(), s.custodian, ErrorCode::Unauthorized);
-        s.price_config.sol_usdc_price_feed = feed;
-        s.price_config.enabled = enabled;
+    /// Permissionless crank: value the basket (price - conf, after haircuts) against outstanding
+    /// debt and move the vault between Active, MarginCall and Default. A defaulted vault stays
+    /// in Default after a price rebound; only full repayment or a settled auction clears it.
+    pub fn mark_to_market(ctx: Context<MarkToMarket>) -> Result<()> {
+        let state = &mut ctx.accounts.vault_state;
+        // No clock runs against the borrower while deposits are frozen
+        require!(!state.is_frozen, ErrorCode::Paused);
+        require!(state.price_config.enabled, ErrorCode::PriceChecksDisabled);
+
+        let now = Clock::get()?.unix_timestamp;
+        state.loan.accrue(now)?;
+
+        let prices = load_basket_prices(state, state.key(), &[], ctx.remaining_accounts, now)?;
+        let collateral_quote_1e6 = basket_value_quote_1e6(state, &prices)?;
+        let debt_quote_1e6 = debt_quote_1e6(state.loan.outstanding()?, state.loan.debt_decimals)?;
+        let health = health_bps(collateral_quote_1e6, debt_quote_1e6)?;
+        let healthy = health >= state.price_config.maintenance_ratio_bps as u128;
+
+        let snapshot = HealthSnapshot {
+            collateral_quote_1e6: to_u64(collateral_quote_1e6)?,
+            debt_quote_1e6: to_u64(debt_quote_1e6)?,
+            health_bps: to_u64(health.min(u64::MAX as u128))?,
+        };
+
+        match (state.status, healthy) {
+            (VaultStatus::Active, false) => {
+                let deadline = now
+                    .checked_add(state.price_config.margin_cure_secs)
+                    .ok_or(ErrorCode::MathOverflow)?;
+                state.status = VaultStatus::MarginCall;
+                state.margin_call_deadline = deadline;
+                emit!(MarginCallIssued { health: snapshot, deadline });
+            }
+            // Only a margin call is cured by re-marking; Default ends by repayment or auction
+            (VaultStatus::MarginCall, true) => {
+                state.status = VaultStatus::Active;
+                state.margin_call_deadline = 0;
+                emit!(MarginCallCured { health: snapshot });
+            }
+            (VaultStatus::MarginCall, false) if now >= state.margin_call_deadline => {
+                state.status = VaultStatus::Default;
+                emit!(VaultDefaulted { health: snapshot });
+            }
+            // Liquidating only leaves via settle_auction
+            _ => {}
+        }
+
         Ok(())
     }
 
-    /// Admin: set risk limits for USD caps & staleness; and maximum LTV (bps).
-    pub fn set_risk_limits(
-        ctx: Context<Admin>,
-        max_ltv_bps: u16,
-        max_single_usd_1e6: u64,
-        daily_cap_usd_1e6: u64,
-        max_price_staleness_secs: i64,
+    /// Start a Dutch auction of all locked collateral of one basket mint once the vault is in
+    /// Default; remaining mints are auctioned one after another until the debt is cleared.
+    /// Permissionless; the starter funds (and later recovers) the auction account rent.
+    pub fn start_auction<'info>(
+        ctx: Context<'_, '_, '_, 'info, StartAuction<'info>>,
     ) -> Result<()> {
-        let s = &mut ctx.accounts.vault_state;
-        require_keys_eq!(ctx.accounts.custodian.key(), s.custodian, ErrorCode::Unauthorized);
-        require!(max_ltv_bps <= 9_999, ErrorCode::InvalidRiskParams); // disallow 100%+
-        s.price_config.max_ltv_bps = max_ltv_bps;
-        s.price_config.max_single_release_usd_1e6 = max_single_usd_1e6;
-        s.price_config.daily_cap_usd_1e6 = daily_cap_usd_1e6;
-        s.price_config.max_price_staleness_secs = max_price_staleness_secs;
+        let state = &mut ctx.accounts.vault_state;
+        require!(!state.is_frozen, ErrorCode::Paused);
+        require!(state.status == VaultStatus::Default, ErrorCode::NotInDefault);
+        let idx = asset_index(state, ctx.accounts.mint_account.key())?;
+        let asset = state.basket[idx];
+        require!(asset.amount_locked > 0, ErrorCode::InvalidAmount);
+
+        let now = Clock::get()?.unix_timestamp;
+        state.loan.accrue(now)?;
+
+        // Spot price per whole collateral token (quote 1e6); auctions quote spot, not price - conf
+        let px = load_asset_price(
+            state,
+            state.key(),
+            &asset,
+            ctx.remaining_accounts,
+            now,
+        )?
+        .spot;
+        let mints = [ctx.accounts.mint_account.to_account_info()];
+        let px = ui_price(&asset, px, &mints, ctx.remaining_accounts, now)?;
+        require!(px.price > 0, ErrorCode::PriceNonPositive);
+        let oracle_1e6 = price_per_token_1e6(px.price as u128, px.expo)?;
+        let cfg = &state.price_config;
+        let start_price_1e6 =
+            to_u64(apply_bps(oracle_1e6, 10_000 + cfg.auction_premium_bps as u128)?)?;
+        let floor_price_1e6 = to_u64(apply_bps(oracle_1e6, cfg.auction_floor_bps as u128)?)?;
+
+        let debt_target = state.loan.outstanding()?;
+        let fee_target =
+            to_u64(apply_bps(debt_target as u128, cfg.liquidation_fee_bps as u128)?)?;
+        let end_ts = now
+            .checked_add(cfg.auction_duration_secs)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let auction = &mut ctx.accounts.auction;
+        auction.vault = state.key();
+        auction.starter = ctx.accounts.starter.key();
+        auction.mint = asset.mint;
+        auction.start_ts = now;
+        auction.end_ts = end_ts;
+        auction.start_price_1e6 = start_price_1e6;
+        auction.floor_price_1e6 = floor_price_1e6;
+        auction.collateral_for_sale = asset.amount_locked;
+        auction.collateral_sold = 0;
+        auction.debt_target = debt_target;
+        auction.fee_target = fee_target;
+        auction.proceeds = 0;
+        auction.bump = ctx.bumps.auction;
+
+        state.status = VaultStatus::Liquidating;
+
+        emit!(AuctionStarted {
+            auction: auction.key(),
+            mint: asset.mint,
+            start_price_1e6,
+            floor_price_1e6,
+            end_ts,
+            collateral_for_sale: auction.collateral_for_sale,
+            debt_target,
+            fee_target,
+        });
         Ok(())
     }
 
-    /// Close the vault account when fully drained; refunds rent to `recipient`.
-    pub fn close_vault(_ctx: Context<CloseVault>) -> Result<()> {
-        // All checks are in the account constraints
+    /// Buy up to `max_collateral` at the current auction price, paying in the debt mint.
+    /// The bidder bears a Token-2022 transfer fee on the debt mint; proceeds are booked from
+    /// what the proceeds ATA actually received. Sales stop once proceeds cover the debt plus
+    /// the liquidation fee. Transfer-hook extra accounts of either mint are passed as
+    /// remaining accounts.
+    pub fn bid<'info>(
+        ctx: Context<'_, '_, '_, 'info, Bid<'info>>,
+        max_collateral: u64,
+        max_price_1e6: u64,
+    ) -> Result<()> {
+        require!(max_collateral > 0, ErrorCode::InvalidAmount);
+        let state = &mut ctx.accounts.vault_state;
+        require!(!state.is_frozen, ErrorCode::Paused);
+        let auction = &mut ctx.accounts.auction;
+        let idx = asset_index(state, auction.mint)?;
+        let mint_decimals = state.basket[idx].decimals;
+
+        let clock = Clock::get()?;
+        let now = clock.unix_timestamp;
+        let price_1e6 = auction.current_price_1e6(now)?;
+        require!(price_1e6 <= max_price_1e6, ErrorCode::AuctionPriceAboveLimit);
+
+        // Cap by what is left and by what is still needed to cover debt + fee
+        let unsold = auction
+            .collateral_for_sale
+            .checked_sub(auction.collateral_sold)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let needed = auction
+            .debt_target
+            .checked_add(auction.fee_target)
+            .ok_or(ErrorCode::MathOverflow)?
+            .saturating_sub(auction.proceeds);
+        require!(needed > 0, ErrorCode::AuctionNotFinished);
+        let for_needed = auction_tokens_for(
+            needed,
+            mint_decimals,
+            state.loan.debt_decimals,
+            price_1e6,
+        )?
+        .max(1);
+        let collateral_out = max_collateral.min(unsold).min(for_needed);
+        require!(collateral_out > 0, ErrorCode::InvalidAmount);
+        let cost = auction_cost(
+            collateral_out,
+            mint_decimals,
+            state.loan.debt_decimals,
+            price_1e6,
+        )?;
+
+        // Extra ownership pinning
+        require_keys_eq!(
+            ctx.accounts.vault_ata.owner,
+            ctx.accounts.vault_authority.key(),
+            ErrorCode::Unauthorized
+        );
+
+        // Payment: bidder_debt_ata -> proceeds_ata (held by vault_authority until settle),
+        // grossed up so that `cost` arrives after a transfer fee
+        let debt_mint_info = ctx.accounts.debt_mint_account.to_account_info();
+        let paid = gross_up_for_fee(&debt_mint_info, cost, clock.epoch)?;
+        let proceeds_before = ctx.accounts.proceeds_ata.amount;
+        spl_token_2022::onchain::invoke_transfer_checked(
+            ctx.accounts.debt_token_program.key,
+            ctx.accounts.bidder_debt_ata.to_account_info(),
+            debt_mint_info,
+            ctx.accounts.proceeds_ata.to_account_info(),
+            ctx.accounts.bidder.to_account_info(),
+            ctx.remaining_accounts,
+            paid,
+            ctx.accounts.debt_mint_account.decimals,
+            &[],
+        )?;
+
+        // Book what arrived, not what was sent
+        ctx.accounts.proceeds_ata.reload()?;
+        let received = ctx
+            .accounts
+            .proceeds_ata
+            .amount
+            .checked_sub(proceeds_before)
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(received > 0, ErrorCode::InvalidAmount);
+
+        // ---- PDA signer seeds (stable lifetime binding) ----
+        let state_key = state.key();
+        let signer_seed_slice: [&[u8]; 3] = [
+            SEED_AUTH,
+            state_key.as_ref(),
+            &[state.vault_authority_bump],
+        ];
+        let signer: &[&[u8]] = &signer_seed_slice;
+        let signer_arr: &[&[&[u8]]] = &[signer];
+        // ----------------------------------------------------
+
+        // Delivery: vault_ata -> bidder_collateral_ata
+        spl_token_2022::onchain::invoke_transfer_checked(
+            ctx.accounts.token_program.key,
+            ctx.accounts.vault_ata.to_account_info(),
+            ctx.accounts.mint_account.to_account_info(),
+            ctx.accounts.bidder_collateral_ata.to_account_info(),
+            ctx.accounts.vault_authority.to_account_info(),
+            ctx.remaining_accounts,
+            collateral_out,
+            ctx.accounts.mint_account.decimals,
+            signer_arr,
+        )?;
+
+        auction.collateral_sold = auction
+            .collateral_sold
+            .checked_add(collateral_out)
+            .ok_or(ErrorCode::MathOverflow)?;
+        auction.proceeds = auction
+            .proceeds
+            .checked_add(received)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let asset = &mut state.basket[idx];
+        asset.amount_locked = asset
+            .amount_locked
+            .checked_sub(collateral_out)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(AuctionBid {
+            auction: auction.key(),
+            bidder: ctx.accounts.bidder.key(),
+            price_1e6,
+            collateral_out,
+            cost,
+            paid,
+            received,
+            proceeds: auction.proceeds,
+        });
         Ok(())
     }
-}
 
-/* -------------------------------- Accounts -------------------------------- */
+    /// Distribute auction proceeds: lender's outstanding debt first, then the custodian's
+    /// liquidation fee, surplus to the borrower. Permissionless once the collateral is sold,
+    /// debt + fee are covered, or the auction has run its course. A transfer-hook debt mint's
+    /// extra accounts are passed as remaining accounts.
+    pub fn settle_auction<'info>(
+        ctx: Context<'_, '_, '_, 'info, SettleAuction<'info>>,
+    ) -> Result<()> {
+        let state = &mut ctx.accounts.vault_state;
+        let auction = &ctx.accounts.auction;
 
-#[derive(Accounts)]
-#[instruction(custodian: Pubkey, borrower: Pubkey, lender: Pubkey, mint: Pubkey)]
-pub struct InitializeVault<'info> {
-    #[account(
-        init,
-        payer = payer,
-        space = 8 + VaultState::SIZE,
-        seeds = [
-            SEED_VAULT,
-            mint.as_ref(),
-            custodian.as_ref(),
-            borrower.as_ref(),
-            lender.as_ref()
-        ],
-        bump
-    )]
-    pub vault_state: Account<'info, VaultState>,
+        let now = Clock::get()?.unix_timestamp;
+        let covered = auction.proceeds
+            >= auction
+                .debt_target
+                .checked_add(auction.fee_target)
+                .ok_or(ErrorCode::MathOverflow)?;
+        let sold_out = auction.collateral_sold == auction.collateral_for_sale;
+        require!(
+            covered || sold_out || now >= auction.end_ts,
+            ErrorCode::AuctionNotFinished
+        );
+        state.loan.accrue(now)?;
 
-    /// CHECK: PDA with no data; used as CPI signer
-    #[account(
-        seeds = [SEED_AUTH, vault_state.key().as_ref()],
-        bump
-    )]
-    pub vault_authority: UncheckedAccount<'info>,
+        // Waterfall
+        let to_lender = auction.proceeds.min(state.loan.outstanding()?);
+        let after_lender = auction.proceeds - to_lender;
+        let to_custodian = after_lender.min(auction.fee_target);
+        let to_borrower = after_lender - to_custodian;
 
-    /// Mint of the collateral token
-    pub mint_account: Account<'info, Mint>,
+        // ---- PDA signer seeds (stable lifetime binding) ----
+        let state_key = state.key();
+        let signer_seed_slice: [&[u8]; 3] = [
+            SEED_AUTH,
+            state_key.as_ref(),
+            &[state.vault_authority_bump],
+        ];
+        let signer: &[&[u8]] = &signer_seed_slice;
+        let signer_arr: &[&[&[u8]]] = &[signer];
+        // ----------------------------------------------------
 
-    /// Vault ATA = ATA(mint, vault_authority)
-    #[account(
-        init_if_needed,
-        payer = payer,
-        associated_token::mint = mint_account,
-        associated_token::authority = vault_authority
-    )]
-    pub vault_ata: Account<'info, TokenAccount>,
+        for (to, amount) in [
+            (ctx.accounts.lender_debt_ata.to_account_info(), to_lender),
+            (ctx.accounts.custodian_debt_ata.to_account_info(), to_custodian),
+            (ctx.accounts.borrower_debt_ata.to_account_info(), to_borrower),
+        ] {
+            if amount == 0 {
+                continue;
+            }
+            spl_token_2022::onchain::invoke_transfer_checked(
+                ctx.accounts.debt_token_program.key,
+                ctx.accounts.proceeds_ata.to_account_info(),
+                ctx.accounts.debt_mint_account.to_account_info(),
+                to,
+                ctx.accounts.vault_authority.to_account_info(),
+                ctx.remaining_accounts,
+                amount,
+                ctx.accounts.debt_mint_account.decimals,
+                signer_arr,
+            )?;
+        }
 
-    /// Payer for initialization (can be any signer)
-    #[account(mut)]
-    pub payer: Signer<'info>,
+        state.loan.apply_payment(to_lender)?;
+        // Debt cleared -> back to normal; otherwise remain in Default (re-auction possible)
+        if state.loan.outstanding()? == 0 {
+            state.status = VaultStatus::Active;
+            state.margin_call_deadline = 0;
+        } else {
+            state.status = VaultStatus::Default;
+        }
 
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-}
+        emit!(AuctionSettled {
+            auction: auction.key(),
+            collateral_sold: auction.collateral_sold,
+            proceeds: auction.proceeds,
+            to_lender,
+            to_custodian,
+            to_borrower,
+            outstanding_after: state.loan.outstanding()?,
+        });
+        Ok(())
+    }
 
-#[derive(Accounts)]
-pub struct DepositCollateral<'info> {
-    #[account(mut)]
-    pub vault_state: Account<'info, VaultState>,
+    /// Replace the collateral eligibility schedule agreed by custodian and lender (created on
+    /// first use). Feeds and haircuts of unfunded basket mints still listed are updated in
+    /// place; funded mints must keep theirs (see `SetAssetOracle`). Delisted mints keep their
+    /// collateral but accept no further deposits.
+    pub fn set_eligibility_schedule(
+        ctx: Context<SetEligibilitySchedule>,
+        entries: Vec<EligibleCollateral>,
+    ) -> Result<()> {
+        let state = &mut ctx.accounts.vault_state;
+        require_keys_eq!(ctx.accounts.custodian.key(), state.custodian, ErrorCode::Unauthorized);
+        require_keys_eq!(ctx.accounts.lender.key(), state.lender, ErrorCode::Unauthorized);
+        validate_eligibility(&entries)?;
+        let now = Clock::get()?.unix_timestamp;
+        state.loan.accrue(now)?;
 
-    /// CHECK: PDA signer (not invoked here, but we enforce ATA owner below)
-    #[account(
-        seeds = [SEED_AUTH, vault_state.key().as_ref()],
-        bump = vault_state.vault_authority_bump
-    )]
-    pub vault_authority: UncheckedAccount<'info>,
+        for asset in state.basket.iter_mut() {
+            if let Some(e) = entries.iter().find(|e| e.mint == asset.mint) {
+                // Funded mints change feeds and haircut only via `SetAssetOracle` (timelocked)
+                if asset.amount_locked > 0 {
+                    require!(
+                        e.price_feed == asset.price_feed
+                            && e.feed_id == asset.feed_id
+                            && e.switchboard_feed == asset.switchboard_feed
+                            && e.haircut_bps == asset.haircut_bps,
+                        ErrorCode::AssetOracleChangeNeedsGovernance
+                    );
+                    continue;
+                }
+                asset.price_feed = e.price_feed;
+                asset.feed_id = e.feed_id;
+                asset.switchboard_feed = e.switchboard_feed;
+                asset.haircut_bps = e.haircut_bps;
+            }
+        }
 
-    #[account(
-        mut,
-        associated_token::mint = mint_account,
-        associated_token::authority = vault_authority
-    )]
-    pub vault_ata: Account<'info, TokenAccount>,
+        let schedule = &mut ctx.accounts.eligibility_schedule;
+        // First use: the schedule is closed along with the vault
+        if schedule.vault == Pubkey::default() {
+            state.open_aux_accounts = state
+                .open_aux_accounts
+                .checked_add(1)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+        schedule.vault = state.key();
+        schedule.entries = entries;
+        schedule.updated_at = now;
+        schedule.bump = ctx.bumps.eligibility_schedule;
 
-    #[account(
-        constraint = mint_account.key() == vault_state.mint @ ErrorCode::Unauthorized
-    )]
-    pub mint_account: Account<'info, Mint>,
+        emit!(EligibilityScheduleSet {
+            schedule: schedule.key(),
+            entries: schedule.entries.clone(),
+        });
+        Ok(())
+    }
 
-    /// Depositor must be one of the three roles
-    pub depositor: Signer<'info>,
+    /// Custodian attests a USD price for one mint (`price * 10^expo`), read when
+    /// `PriceConfig::oracle_sources` lists `Manual`. Created on first use. A move of more
+    /// than `manual_max_change_bps` from the reference price needs the lender's co-signature.
+    /// The reference is the last co-signed price, re-anchored to the latest price once per
+    /// `manual_change_window_secs`, so repeated posts can't chain small steps into a big move.
+    /// Every update is logged so the borrower can dispute it.
+    pub fn post_manual_price(ctx: Context<PostManualPrice>, price: i64, expo: i32) -> Result<()> {
+        let state = &mut ctx.accounts.vault_state;
+        require_keys_eq!(ctx.accounts.custodian.key(), state.custodian, ErrorCode::Unauthorized);
+        let lender_cosigned = match &ctx.accounts.lender {
+            Some(lender) => {
+                require_keys_eq!(lender.key(), state.lender, ErrorCode::Unauthorized);
+                true
+            }
+            None => false,
+        };
+        require!(price > 0, ErrorCode::PriceNonPositive);
+        require!((-18..=0).contains(&expo), ErrorCode::InvalidRiskParams);
+        let now = Clock::get()?.unix_timestamp;
 
-    #[account(
-        mut,
-        constraint = depositor_ata.owner == depositor.key(),
-        constraint = depositor_ata.mint == mint_account.key()
-    )]
-    pub depositor_ata: Account<'info, TokenAccount>,
+        // Only mints the vault can value: basket, eligibility schedule, or the debt mint
+        // (the quote cross rate)
+        let mint = ctx.accounts.mint_account.key();
+        let listed = state.basket.iter().any(|a| a.mint == mint)
+            || mint == state.loan.debt_mint
+            || ctx
+                .accounts
+                .eligibility_schedule
+                .as_ref()
+                .is_some_and(|s| s.entries.iter().any(|e| e.mint == mint));
+        require!(listed, ErrorCode::MintNotEligible);
 
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-}
+        // First use: the account is closed along with the vault
+        if ctx.accounts.manual_price.vault == Pubkey::default() {
+            state.open_aux_accounts = state
+                .open_aux_accounts
+                .checked_add(1)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+
+        let manual = &mut ctx.accounts.manual_price;
+        let (prev_price, prev_expo) = (manual.price, manual.expo);
+        manual.post(&state.price_config, price, expo, lender_cosigned, now)?;
+        manual.vault = state.key();
+        manual.mint = mint;
+        manual.bump = ctx.bumps.manual_price;
+
+        emit!(ManualPricePosted {
+            manual_price: manual.key(),
+            mint: manual.mint,
+            prev_price,
+            prev_expo,
+            ref_price: manual.ref_price,
+            ref_expo: manual.ref_expo,
+            price,
+            expo,
+            publish_time: now,
+            posted_by: ctx.accounts.custodian.key(),
+            lender_cosigned,
+        });
+        Ok(())
+    }
+
+    /// Permissionless crank: append the aggregated spot price of one basket mint to its TWAP
+    /// ring buffer (created on first use; the cranker pays the rent). At most one
+    /// observation per `twap_min_interval_secs`.
+    pub fn record_price_observation(ctx: Context<RecordPriceObservation>) -> Result<()> {
+        let state = &mut ctx.accounts.vault_state;
+        require!(state.price_config.enabled, ErrorCode::PriceChecksDisabled);
+        let idx = asset_index(state, ctx.accounts.mint_account.key())?;
+        let now = Clock::get()?.unix_timestamp;
+
+        // First use: the buffer is closed along with the vault
+        if ctx.accounts.price_observations.vault == Pubkey::default() {
+            state.open_aux_accounts = state
+                .open_aux_accounts
+                .checked_add(1)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+
+        let observations = &mut ctx.accounts.price_observations;
+        // Start over in the current quote currency
+        if observations.quote_epoch != state.quote_epoch {
+            observations.clear();
+            observations.quote_epoch = state.quote_epoch;
+        }
+        if let Some(last) = observations.newest() {
+            let next_ts = last
+                .ts
+                .checked_add(state.price_config.twap_min_interval_secs)
+                .ok_or(ErrorCode::MathOverflow)?;
+            require!(now >= next_ts, ErrorCode::ObservationTooSoon);
+        }
+
+        let px = load_asset_price(
+            state,
+            state.key(),
+            &state.basket[idx],
+            ctx.remaining_accounts,
+            now,
+        )?
+        .spot;
+        let (price, conf) = rescale_price(&px, TWAP_EXPO)?;
+
+        observations.vault = state.key();
+        observations.mint = state.basket[idx].mint;
+        observations.bump = ctx.bumps.price_observations;
+        observations.push(PriceObservation {
+            ts: now,
+            price,
+            conf,
+        });
+
+        emit!(PriceObserved {
+            observations: observations.key(),
+            mint: observations.mint,
+            price,
+            conf,
+            expo: TWAP_EXPO,
+            ts: now,
+        });
+        Ok(())
+    }
+
+    /// Configure (or clear with `Pubkey::default()`) the non-role key allowed to execute
+    /// approved releases; all three roles must sign.
+    pub fn set_executor(ctx: Context<AllRoles>, executor: Pubkey) -> Result<()> {
+        let state = &mut ctx.accounts.vault_state;
+        require_keys_eq!(ctx.accounts.custodian.key(), state.custodian, ErrorCode::Unauthorized);
+        require_keys_eq!(ctx.accounts.borrower.key(), state.borrower, ErrorCode::Unauthorized);
+        require_keys_eq!(ctx.accounts.lender.key(), state.lender, ErrorCode::Unauthorized);
+        state.loan.accrue(Clock::get()?.unix_timestamp)?;
+
+        let old = state.executor;
+        state.executor = executor;
+        emit!(ExecutorSet { old, new: executor });
+        Ok(())
+    }
+
+    /// Pause guard: only custodian can pause.
+    pub fn pause(ctx: Context<Pause>) -> Result<()> {
+        let state = &mut ctx.accounts.vault_state;
+        require_keys_eq!(
+            ctx.accounts.custodian.key(),
+            state.custodian,
+            ErrorCode::Unauthorized
+        );
+        let now = Clock::get()?.unix_timestamp;
+        freeze(state, now);
+        state.loan.accrue(now)?;
+        emit!(Paused {});
+        emit!(StateSignal {
+            paused: true,
+            open_release_proposals: state.open_release_proposals,
+            basket_assets: state.basket.len() as u8
+        });
+        Ok(())
+    }
+
+    /// Unpause guard: only custodian can unpause. The time spent frozen is added to the
+    /// margin-call deadline, or to the auction clock while liquidating (pass the auction),
+    /// since the borrower could not cure and bidders could not bid meanwhile.
+    pub fn unpause(ctx: Context<Pause>) -> Result<()> {
+        let state = &mut ctx.accounts.vault_state;
+        require_keys_eq!(
+            ctx.accounts.custodian.key(),
+            state.custodian,
+            ErrorCode::Unauthorized
+        );
+        let now = Clock::get()?.unix_timestamp;
+        let frozen_secs = if state.is_frozen {
+            now.saturating_sub(state.frozen_at).max(0)
+        } else {
+            0
+        };
+        match state.status {
+            VaultStatus::MarginCall => {
+                state.margin_call_deadline = state
+                    .margin_call_deadline
+                    .checked_add(frozen_secs)
+                    .ok_or(ErrorCode::MathOverflow)?;
+            }
+            VaultStatus::Liquidating => {
+                let auction = ctx
+                    .accounts
+                    .auction
+                    .as_mut()
+                    .ok_or(ErrorCode::AuctionAccountMissing)?;
+                auction.start_ts = auction
+                    .start_ts
+                    .checked_add(frozen_secs)
+                    .ok_or(ErrorCode::MathOverflow)?;
+                auction.end_ts = auction
+                    .end_ts
+                    .checked_add(frozen_secs)
+                    .ok_or(ErrorCode::MathOverflow)?;
+            }
+            _ => {}
+        }
+        state.is_frozen = false;
+        state.frozen_at = 0;
+        // Re-baseline the price-move breaker at the next accepted prices
+        for asset in state.basket.iter_mut() {
+            asset.last_price_ts = 0;
+        }
+        state.loan.accrue(now)?;
+        emit!(Unpaused { frozen_secs });
+        emit!(StateSignal {
+            paused: false,
+            open_release_proposals: state.open_release_proposals,
+            basket_assets: state.basket.len() as u8
+        });
+        Ok(())
+    }
+
+    /// Clear a release proposal's approvals bitmap (custodian-only).
+    pub fn reset_approvals(ctx: Context<ResetApprovals>) -> Result<()> {
+        let state = &ctx.accounts.vault_state;
+        require_keys_eq!(
+            ctx.accounts.custodian.key(),
+            state.custodian,
+            ErrorCode::Unauthorized
+        );
+        let proposal = &mut ctx.accounts.release_proposal;
+        let cleared_bitmap = proposal.approvals_bitmap;
+        proposal.approvals_bitmap = 0;
+        emit!(ApprovalsReset {
+            proposal: proposal.key(),
+            cleared_bitmap,
+            reset_by: ctx.accounts.custodian.key(),
+        });
+        Ok(())
+    }
+
+    /// Open a governance proposal (e.g. role rotation); the proposer's approval is recorded
+    /// immediately.
+    pub fn propose_governance(
+        ctx: Context<ProposeGovernance>,
+        role: u8,
+        action: GovernanceAction,
+        expires_at: i64,
+    ) -> Result<()> {
+        let state = &mut ctx.accounts.vault_state;
+
+        // Auth: signer must match role
+        let expected = role_key(state, role)?;
+        require_keys_eq!(ctx.accounts.proposer.key(), expected, ErrorCode::Unauthorized);
+
+        validate_governance_action(state, &action)?;
+
+        let now = Clock::get()?.unix_timestamp;
+        check_new_expiry(now, expires_at)?;
+        state.loan.accrue(now)?;
+
+        // Risk/oracle changes wait out the timelock so every role can see (and veto) them
+        let eta = if is_timelocked(&action) {
+            now.checked_add(state.risk_timelock_secs)
+                .ok_or(ErrorCode::MathOverflow)?
+        } else {
+            now
+        };
+        require!(eta < expires_at, ErrorCode::InvalidExpiry);
+
+        let nonce = state.governance_nonce;
+        let proposal = &mut ctx.accounts.governance_proposal;
+        proposal.vault = state.key();
+        proposal.nonce = nonce;
+        proposal.proposer = ctx.accounts.proposer.key();
+        proposal.action = action;
+        proposal.eta = eta;
+        proposal.expires_at = expires_at;
+        proposal.roles_epoch = state.roles_epoch;
+        proposal.approvals_bitmap = 0;
+        set_bit(&mut proposal.approvals_bitmap, role);
+        proposal.bump = ctx.bumps.governance_proposal;
+
+        state.governance_nonce = nonce.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(GovernanceProposed {
+            proposal: proposal.key(),
+            nonce,
+            proposer: proposal.proposer,
+            action,
+            eta,
+            expires_at,
+        });
+        if let GovernanceAction::SetPriceConfig { config } = action {
+            emit!(PriceConfigProposed {
+                proposal: proposal.key(),
+                old: state.price_config,
+                new: config,
+                eta,
+            });
+        }
+        Ok(())
+    }
+
+    /// Any single role may kill a timelocked proposal while its delay is running.
+    pub fn veto_governance(ctx: Context<CancelGovernance>, role: u8) -> Result<()> {
+        let state = &ctx.accounts.vault_state;
+        let expected = role_key(state, role)?;
+        require_keys_eq!(ctx.accounts.role_signer.key(), expected, ErrorCode::Unauthorized);
+
+        let proposal = &ctx.accounts.governance_proposal;
+        require!(is_timelocked(&proposal.action), ErrorCode::WrongProposalAction);
+        let now = Clock::get()?.unix_timestamp;
+        require!(now < proposal.eta, ErrorCode::VetoWindowClosed);
+
+        emit!(GovernanceVetoed {
+            proposal: proposal.key(),
+            nonce: proposal.nonce,
+            by_role: role,
+        });
+        Ok(())
+    }
+
+    /// Role-gated approval of a governance proposal; idempotent bit set.
+    pub fn approve_governance(ctx: Context<ApproveGovernance>, role: u8) -> Result<()> {
+        let state = &ctx.accounts.vault_state;
+        let expected = role_key(state, role)?;
+        require_keys_eq!(ctx.accounts.role_signer.key(), expected, ErrorCode::Unauthorized);
+
+        let proposal = &mut ctx.accounts.governance_proposal;
+        require!(proposal.roles_epoch == state.roles_epoch, ErrorCode::StaleProposal);
+        let now = Clock::get()?.unix_timestamp;
+        require!(now < proposal.expires_at, ErrorCode::ProposalExpired);
+
+        if !has_bit(proposal.approvals_bitmap, role) {
+            set_bit(&mut proposal.approvals_bitmap, role);
+        }
+        emit!(GovernanceApproved {
+            proposal: proposal.key(),
+            by_role: role,
+            approvals_bitmap: proposal.approvals_bitmap
+        });
+        Ok(())
+    }
+
+    /// Allow a role to revoke its approval of a governance proposal.
+    pub fn revoke_governance(ctx: Context<ApproveGovernance>, role: u8) -> Result<()> {
+        let state = &ctx.accounts.vault_state;
+        let expected = role_key(state, role)?;
+        require_keys_eq!(ctx.accounts.role_signer.key(), expected, ErrorCode::Unauthorized);
+
+        let proposal = &mut ctx.accounts.governance_proposal;
+        if has_bit(proposal.approvals_bitmap, role) {
+            clear_bit(&mut proposal.approvals_bitmap, role);
+            emit!(GovernanceApproved {
+                proposal: proposal.key(),
+                by_role: role,
+                approvals_bitmap: proposal.approvals_bitmap
+            });
+        }
+        Ok(())
+    }
+
+    /// Close a governance proposal without executing it. The proposer may cancel at any
+    /// time; any role may clean up once the proposal has expired or gone stale.
+    pub fn cancel_governance(ctx: Context<CancelGovernance>) -> Result<()> {
+        let state = &ctx.accounts.vault_state;
+        let proposal = &ctx.accounts.governance_proposal;
+        let signer = ctx.accounts.role_signer.key();
+
+        let now = Clock::get()?.unix_timestamp;
+        let dead = now >= proposal.expires_at || proposal.roles_epoch != state.roles_epoch;
+        require!(
+            signer == proposal.proposer || (dead && is_role(state, signer)),
+            ErrorCode::Unauthorized
+        );
+
+        emit!(GovernanceCancelled {
+            proposal: proposal.key(),
+            nonce: proposal.nonce,
+            cancelled_by: signer,
+        });
+        Ok(())
+    }
+
+    /// Second step of a role rotation: the incoming key accepts an approved `RotateRole`
+    /// proposal, which swaps the seat and invalidates approvals made under the old roles.
+    pub fn accept_role(ctx: Context<AcceptRole>) -> Result<()> {
+        let proposal = &ctx.accounts.governance_proposal;
+        let state = &mut ctx.accounts.vault_state;
+
+        let (role, new_key) = match proposal.action {
+            GovernanceAction::RotateRole { role, new_key } => (role, new_key),
+            _ => return err!(ErrorCode::WrongProposalAction),
+        };
+        require_keys_eq!(ctx.accounts.new_holder.key(), new_key, ErrorCode::Unauthorized);
+
+        require!(proposal.roles_epoch == state.roles_epoch, ErrorCode::StaleProposal);
+        let now = Clock::get()?.unix_timestamp;
+        require!(now < proposal.expires_at, ErrorCode::ProposalExpired);
+        require_governance_quorum(state, proposal)?;
+        state.loan.accrue(now)?;
+
+        // Roles may have changed since the proposal was opened
+        validate_governance_action(state, &proposal.action)?;
+
+        let old_holder = role_key(state, role)?;
+        match role {
+            0 => state.custodian = new_key,
+            1 => state.borrower = new_key,
+            _ => state.lender = new_key,
+        }
+        // Outstanding approvals were given by the previous role set
+        state.roles_epoch = state
+            .roles_epoch
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(RoleRotated {
+            proposal: proposal.key(),
+            role,
+            old_holder,
+            new_holder: new_key,
+            roles_epoch: state.roles_epoch,
+            at: now,
+        });
+        Ok(())
+    }
+
+    /// Apply an approved governance proposal (anything but `RotateRole` and `CloseVault`,
+    /// which need `accept_role` / `close_vault`). Any role may execute; the proposal is
+    /// closed on success.
+    pub fn execute_governance(ctx: Context<ExecuteGovernance>) -> Result<()> {
+        let proposal = &ctx.accounts.governance_proposal;
+        let state = &mut ctx.accounts.vault_state;
+
+        let executed_by = ctx.accounts.role_signer.key();
+        require!(is_role(state, executed_by), ErrorCode::Unauthorized);
+
+        require!(proposal.roles_epoch == state.roles_epoch, ErrorCode::StaleProposal);
+        let now = Clock::get()?.unix_timestamp;
+        require!(now < proposal.expires_at, ErrorCode::ProposalExpired);
+        require!(now >= proposal.eta, ErrorCode::TimelockActive);
+        require_governance_quorum(state, proposal)?;
+        state.loan.accrue(now)?;
+
+        // Parameters may have changed since the proposal was opened
+        validate_governance_action(state, &proposal.action)?;
+
+        match proposal.action {
+            GovernanceAction::RotateRole { .. } | GovernanceAction::CloseVault { .. } => {
+                return err!(ErrorCode::WrongProposalAction)
+            }
+            GovernanceAction::SetThreshold { threshold } => {
+                let old = state.threshold;
+                state.threshold = threshold;
+                emit!(ThresholdChanged { old, new: threshold });
+            }
+            GovernanceAction::SetQuorum { action, rule } => {
+                let slot = state.quorum.rule_mut(action);
+                let old = *slot;
+                *slot = rule;
+                emit!(QuorumRuleChanged { action, old, new: rule });
+            }
+            GovernanceAction::SetPriceConfig { config } => {
+                let old = state.price_config;
+                // Observations recorded in the old quote currency must not be averaged in
+                if config.quote != old.quote {
+                    state.quote_epoch =
+                        state.quote_epoch.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+                }
+                state.price_config = config;
+                emit!(PriceConfigChanged { old, new: config });
+            }
+            GovernanceAction::SetRiskTimelock { secs } => {
+                let old = state.risk_timelock_secs;
+                state.risk_timelock_secs = secs;
+                emit!(RiskTimelockChanged { old, new: secs });
+            }
+            GovernanceAction::SetAssetOracle {
+                mint,
+                price_feed,
+                feed_id,
+                switchboard_feed,
+                haircut_bps,
+            } => {
+                let idx = asset_index(state, mint)?;
+                let asset = &mut state.basket[idx];
+                let old = *asset;
+                asset.price_feed = price_feed;
+                asset.feed_id = feed_id;
+                asset.switchboard_feed = switchboard_feed;
+                asset.haircut_bps = haircut_bps;
+                emit!(AssetOracleChanged {
+                    mint,
+                    old_price_feed: old.price_feed,
+                    old_feed_id: old.feed_id,
+                    old_switchboard_feed: old.switchboard_feed,
+                    old_haircut_bps: old.haircut_bps,
+                    price_feed,
+                    feed_id,
+                    switchboard_feed,
+                    haircut_bps,
+                });
+            }
+        }
+
+        emit!(GovernanceExecuted {
+            proposal: proposal.key(),
+            action: proposal.action,
+            executed_by,
+        });
+        Ok(())
+    }
+
+    /// View: principal plus interest accrued up to now, in debt-mint base units
+    /// (read via simulated transaction return data).
+    pub fn outstanding_debt(ctx: Context<ViewVault>) -> Result<u64> {
+        let mut loan = ctx.accounts.vault_state.loan;
+        loan.accrue(Clock::get()?.unix_timestamp)?;
+        loan.outstanding()
+    }
+
+    /// Close the vault on an approved `CloseVault` proposal once all collateral is released.
+    /// Untracked dust left in the vault ATAs is swept to the agreed party, then every basket
+    /// vault ATA, the auction proceeds ATA, the schedule, manual price and observation PDAs
+    /// and the state are closed with rent refunded to the initialization payer.
+    ///
+    /// Remaining accounts, per basket asset in order and then for the debt mint's proceeds
+    /// ATA (unless the debt mint is in the basket): the ATA and the token program owning its
+    /// mint, followed by the mint and the dust recipient's token account for that mint when
+    /// the ATA is not empty. After them, in any order: every `EligibilitySchedule`,
+    /// `ManualPrice` and `PriceObservations` account of the vault, and a transfer-hook mint's
+    /// extra accounts.
+    pub fn close_vault<'info>(ctx: Context<'_, '_, 'info, 'info, CloseVault<'info>>) -> Result<()> {
+        let proposal = &ctx.accounts.governance_proposal;
+        let state = &ctx.accounts.vault_state;
+
+        let closed_by = ctx.accounts.role_signer.key();
+        require!(is_role(state, closed_by), ErrorCode::Unauthorized);
+
+        let dust_recipient = match proposal.action {
+            GovernanceAction::CloseVault { dust_recipient } => dust_recipient,
+            _ => return err!(ErrorCode::WrongProposalAction),
+        };
+        require!(proposal.roles_epoch == state.roles_epoch, ErrorCode::StaleProposal);
+        let now = Clock::get()?.unix_timestamp;
+        require!(now < proposal.expires_at, ErrorCode::ProposalExpired);
+        require_governance_quorum(state, proposal)?;
+
+        // Everything booked must have gone out through the release flow, and no proposal
+        // may be left holding rent against a closed vault
+        require!(
+            state.basket.iter().all(|a| a.amount_locked == 0),
+            ErrorCode::VaultNotEmpty
+        );
+        require!(state.open_release_proposals == 0, ErrorCode::PendingReleaseFlow);
+        require!(state.status != VaultStatus::Liquidating, ErrorCode::AuctionActive);
+        // Closing must not write off the lender's claim
+        require!(state.loan.outstanding()? == 0, ErrorCode::LoanOutstanding);
+
+        // ---- PDA signer seeds (stable lifetime binding) ----
+        let state_key = state.key();
+        let signer_seed_slice: [&[u8]; 3] = [
+            SEED_AUTH,
+            state_key.as_ref(),
+            &[state.vault_authority_bump],
+        ];
+        let signer: &[&[u8]] = &signer_seed_slice;
+        let signer_arr: &[&[&[u8]]] = &[signer];
+        // ----------------------------------------------------
+
+        // Basket vault ATAs, then the proceeds ATA: ATA(debt mint, vault_authority) is
+        // already among the vault ATAs when the debt mint is collateral too
+        let loan = &state.loan;
+        let proceeds = (!state.basket.iter().any(|a| a.mint == loan.debt_mint))
+            .then_some((loan.debt_mint, loan.debt_token_program, loan.debt_decimals));
+        let atas = state
+            .basket
+            .iter()
+            .map(|a| (a.mint, a.token_program, a.decimals))
+            .chain(proceeds);
+
+        let vault_authority = ctx.accounts.vault_authority.key();
+        let mut remaining = ctx.remaining_accounts.iter();
+        for (mint, token_program, decimals) in atas {
+            let vault_ata_info = remaining.next().ok_or(ErrorCode::VaultNotEmpty)?;
+            let token_program_info = remaining.next().ok_or(ErrorCode::VaultNotEmpty)?;
+            require_keys_eq!(token_program_info.key(), token_program, ErrorCode::Unauthorized);
+            require_keys_eq!(
+                vault_ata_info.key(),
+                get_associated_token_address_with_program_id(
+                    &vault_authority,
+                    &mint,
+                    &token_program
+                ),
+                ErrorCode::Unauthorized
+            );
+            // No auction ever ran: there is no proceeds ATA to close
+            if vault_ata_info.data_is_empty() {
+                continue;
+            }
+            let vault_ata = InterfaceAccount::<TokenAccount>::try_from(vault_ata_info)?;
+
+            // Extra ownership pinning
+            require_keys_eq!(vault_ata.owner, vault_authority, ErrorCode::Unauthorized);
+
+            // Sweep dust (tokens sent straight to the ATA, never booked) to the agreed party
+            let dust_amount = vault_ata.amount;
+            if dust_amount > 0 {
+                require!(dust_recipient != Pubkey::default(), ErrorCode::VaultNotEmpty);
+                let mint_info = remaining.next().ok_or(ErrorCode::ProposalMismatch)?;
+                require_keys_eq!(mint_info.key(), mint, ErrorCode::ProposalMismatch);
+                let dust_ata = InterfaceAccount::<TokenAccount>::try_from(
+                    remaining.next().ok_or(ErrorCode::ProposalMismatch)?,
+                )?;
+                require_keys_eq!(dust_ata.owner, dust_recipient, ErrorCode::ProposalMismatch);
+                require_keys_eq!(dust_ata.mint, mint, ErrorCode::ProposalMismatch);
+
+                spl_token_2022::onchain::invoke_transfer_checked(
+                    token_program_info.key,
+                    vault_ata.to_account_info(),
+                    mint_info.clone(),
+                    dust_ata.to_account_info(),
+                    ctx.accounts.vault_authority.to_account_info(),
+                    ctx.remaining_accounts,
+                    dust_amount,
+                    decimals,
+                    signer_arr,
+                )?;
+            }
+
+            // Close the ATA -> rent back to the initialization payer
+            let cpi_accounts = CloseAccount {
+                account: vault_ata.to_account_info(),
+                destination: ctx.accounts.rent_payer.to_account_info(),
+                authority: ctx.accounts.vault_authority.to_account_info(),
+            };
+            let cpi_ctx = CpiContext::new_with_signer(
+                token_program_info.clone(),
+                cpi_accounts,
+                signer_arr,
+            );
+            token_i::close_account(cpi_ctx)?;
+
+            emit!(VaultAtaClosed {
+                mint,
+                dust_recipient,
+                dust_amount,
+            });
+        }
+
+        // The vault's schedule, manual price and observation PDAs, found by type among the
+        // rest (hook accounts belong to other programs); each may be passed only once
+        let rent_payer = ctx.accounts.rent_payer.to_account_info();
+        let aux_closed = close_aux_accounts(state_key, remaining, &rent_payer)?;
+        require!(aux_closed == state.open_aux_accounts, ErrorCode::VaultNotEmpty);
+
+        emit!(VaultClosed {
+            proposal: proposal.key(),
+            rent_payer: state.rent_payer,
+            dust_recipient,
+            aux_closed,
+            closed_by,
+        });
+        // vault_state and the proposal are closed by their `close` constraints
+        Ok(())
+    }
+}
+
+/* -------------------------------- Accounts -------------------------------- */
+
+#[derive(Accounts)]
+#[instruction(custodian: Pubkey, borrower: Pubkey, lender: Pubkey, mint: Pubkey)]
+pub struct InitializeVault<'info> {
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + VaultState::SIZE,
+        seeds = [
+            SEED_VAULT,
+            mint.as_ref(),
+            custodian.as_ref(),
+            borrower.as_ref(),
+            lender.as_ref()
+        ],
+        bump
+    )]
+    pub vault_state: Account<'info, VaultState>,
+
+    /// CHECK: PDA with no data; used as CPI signer
+    #[account(
+        seeds = [SEED_AUTH, vault_state.key().as_ref()],
+        bump
+    )]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    /// Mint of the first basket collateral token
+    #[account(mint::token_program = token_program)]
+    pub mint_account: InterfaceAccount<'info, Mint>,
+
+    /// Mint the loan is denominated in (e.g. USDC)
+    pub debt_mint_account: InterfaceAccount<'info, Mint>,
+
+    /// Borrower must sign the loan terms
+    #[account(constraint = borrower_signer.key() == borrower @ ErrorCode::Unauthorized)]
+    pub borrower_signer: Signer<'info>,
+
+    /// Lender must sign the loan terms
+    #[account(constraint = lender_signer.key() == lender @ ErrorCode::Unauthorized)]
+    pub lender_signer: Signer<'info>,
+
+    /// Custodian; signs only to accept mint risks (`accepted_mint_risks` != 0)
+    pub custodian_signer: Option<Signer<'info>>,
+
+    /// Vault ATA = ATA(mint, vault_authority)
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = mint_account,
+        associated_token::authority = vault_authority,
+        associated_token::token_program = token_program
+    )]
+    pub vault_ata: InterfaceAccount<'info, TokenAccount>,
+
+    /// Payer for initialization (can be any signer)
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct DepositCollateral<'info> {
+    #[account(mut)]
+    pub vault_state: Account<'info, VaultState>,
+
+    #[account(
+        seeds = [SEED_ELIGIBILITY, vault_state.key().as_ref()],
+        bump = eligibility_schedule.bump
+    )]
+    pub eligibility_schedule: Account<'info, EligibilitySchedule>,
+
+    /// CHECK: PDA signer (not invoked here, but we enforce ATA owner below)
+    #[account(
+        seeds = [SEED_AUTH, vault_state.key().as_ref()],
+        bump = vault_state.vault_authority_bump
+    )]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    /// Vault ATA = ATA(mint, vault_authority); created on a mint's first deposit
+    #[account(
+        init_if_needed,
+        payer = depositor,
+        associated_token::mint = mint_account,
+        associated_token::authority = vault_authority,
+        associated_token::token_program = token_program
+    )]
+    pub vault_ata: InterfaceAccount<'info, TokenAccount>,
+
+    /// Mint being deposited (checked against the eligibility schedule in handler)
+    #[account(mint::token_program = token_program)]
+    pub mint_account: InterfaceAccount<'info, Mint>,
+
+    /// Depositor must be one of the three roles
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = depositor_ata.owner == depositor.key(),
+        constraint = depositor_ata.mint == mint_account.key()
+    )]
+    pub depositor_ata: InterfaceAccount<'info, TokenAccount>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct SubstituteCollateral<'info> {
+    #[account(mut)]
+    pub vault_state: Account<'info, VaultState>,
+
+    #[account(
+        seeds = [SEED_ELIGIBILITY, vault_state.key().as_ref()],
+        bump = eligibility_schedule.bump
+    )]
+    pub eligibility_schedule: Account<'info, EligibilitySchedule>,
+
+    /// CHECK: PDA signer for the outgoing leg
+    #[account(
+        seeds = [SEED_AUTH, vault_state.key().as_ref()],
+        bump = vault_state.vault_authority_bump
+    )]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    /// Incoming mint (checked against the eligibility schedule in handler)
+    #[account(mint::token_program = token_program_in)]
+    pub mint_in: InterfaceAccount<'info, Mint>,
+
+    /// Outgoing basket mint (checked against the basket in handler)
+    #[account(mint::token_program = token_program_out)]
+    pub mint_out: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init_if_needed,
+        payer = borrower,
+        associated_token::mint = mint_in,
+        associated_token::authority = vault_authority,
+        associated_token::token_program = token_program_in
+    )]
+    pub vault_ata_in: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_out,
+        associated_token::authority = vault_authority,
+        associated_token::token_program = token_program_out
+    )]
+    pub vault_ata_out: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut, constraint = borrower.key() == vault_state.borrower @ ErrorCode::Unauthorized)]
+    pub borrower: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = borrower_ata_in.owner == borrower.key(),
+        constraint = borrower_ata_in.mint == mint_in.key()
+    )]
+    pub borrower_ata_in: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = borrower_ata_out.owner == borrower.key(),
+        constraint = borrower_ata_out.mint == mint_out.key()
+    )]
+    pub borrower_ata_out: InterfaceAccount<'info, TokenAccount>,
+
+    pub custodian: Signer<'info>,
+
+    /// Needed only when the swap falls outside the eligibility schedule
+    #[account(constraint = lender.key() == vault_state.lender @ ErrorCode::Unauthorized)]
+    pub lender: Option<Signer<'info>>,
+
+    pub system_program: Program<'info, System>,
+    /// Token programs owning `mint_in` and `mint_out` (may be the same program)
+    pub token_program_in: Interface<'info, TokenInterface>,
+    pub token_program_out: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeRelease<'info> {
+    #[account(mut)]
+    pub vault_state: Account<'info, VaultState>,
+
+    #[account(
+        init,
+        payer = proposer,
+        space = 8 + ReleaseProposal::SIZE,
+        seeds = [
+            SEED_RELEASE,
+            vault_state.key().as_ref(),
+            vault_state.release_nonce.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub release_proposal: Account<'info, ReleaseProposal>,
+
+    /// Proposer must be one of the three roles; pays proposal rent
+    #[account(mut)]
+    pub proposer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveRelease<'info> {
+    pub vault_state: Account<'info, VaultState>,
+
+    #[account(
+        mut,
+        seeds = [
+            SEED_RELEASE,
+            vault_state.key().as_ref(),
+            release_proposal.nonce.to_le_bytes().as_ref()
+        ],
+        bump = release_proposal.bump
+    )]
+    pub release_proposal: Account<'info, ReleaseProposal>,
+
+    /// Any of the three role signers
+    pub role_signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelRelease<'info> {
+    #[account(mut)]
+    pub vault_state: Account<'info, VaultState>,
+
+    #[account(
+        mut,
+        close = proposer,
+        seeds = [
+            SEED_RELEASE,
+            vault_state.key().as_ref(),
+            release_proposal.nonce.to_le_bytes().as_ref()
+        ],
+        bump = release_proposal.bump
+    )]
+    pub release_proposal: Account<'info, ReleaseProposal>,
+
+    /// CHECK: rent refund target; pinned to the proposal's proposer
+    #[account(mut, constraint = proposer.key() == release_proposal.proposer @ ErrorCode::Unauthorized)]
+    pub proposer: UncheckedAccount<'info>,
+
+    /// Proposer, or anyone once the proposal has expired
+    pub role_signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ReleaseCollateral<'info> {
+    #[account(mut)]
+    pub vault_state: Account<'info, VaultState>,
+
+    /// Approved proposal; closed to its proposer once executed (kept open if the price-move
+    /// circuit breaker trips instead)
+    #[account(
+        mut,
+        seeds = [
+            SEED_RELEASE,
+            vault_state.key().as_ref(),
+            release_proposal.nonce.to_le_bytes().as_ref()
+        ],
+        bump = release_proposal.bump
+    )]
+    pub release_proposal: Account<'info, ReleaseProposal>,
+
+    /// CHECK: rent refund target; pinned to the proposal's proposer
+    #[account(mut, constraint = proposer.key() == release_proposal.proposer @ ErrorCode::Unauthorized)]
+    pub proposer: UncheckedAccount<'info>,
+
+    /// CHECK: PDA signer for vault transfers
+    #[account(
+        seeds = [SEED_AUTH, vault_state.key().as_ref()],
+        bump = vault_state.vault_authority_bump
+    )]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_account,
+        associated_token::authority = vault_authority,
+        associated_token::token_program = token_program
+    )]
+    pub vault_ata: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = mint_account.key() == release_proposal.mint @ ErrorCode::ProposalMismatch,
+        mint::token_program = token_program
+    )]
+    pub mint_account: InterfaceAccount<'info, Mint>,
+
+    /// Recipient owner (for event & ATA checks); must be the approved recipient
+    /// CHECK: Only used for key() in event; safety via recipient_ata checks.
+    #[account(constraint = recipient.key() == release_proposal.recipient @ ErrorCode::ProposalMismatch)]
+    pub recipient: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = recipient_ata.owner == recipient.key(),
+        constraint = recipient_ata.mint == mint_account.key()
+    )]
+    pub recipient_ata: InterfaceAccount<'info, TokenAccount>,
+
+    /// One of the three roles, or the configured executor
+    pub executor: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct Pause<'info> {
+    #[account(mut)]
+    pub vault_state: Account<'info, VaultState>,
+    pub custodian: Signer<'info>,
+
+    /// Running auction; `unpause` of a Liquidating vault moves its clock past the freeze
+    #[account(
+        mut,
+        seeds = [SEED_AUCTION, vault_state.key().as_ref()],
+        bump = auction.bump
+    )]
+    pub auction: Option<Account<'info, Auction>>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawExcess<'info> {
+    #[account(mut)]
+    pub vault_state: Account<'info, VaultState>,
+
+    /// CHECK: PDA signer for vault transfers
+    #[account(
+        seeds = [SEED_AUTH, vault_state.key().as_ref()],
+        bump = vault_state.vault_authority_bump
+    )]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_account,
+        associated_token::authority = vault_authority,
+        associated_token::token_program = token_program
+    )]
+    pub vault_ata: InterfaceAccount<'info, TokenAccount>,
+
+    /// Basket mint to withdraw (checked against the basket in handler)
+    #[account(mint::token_program = token_program)]
+    pub mint_account: InterfaceAccount<'info, Mint>,
+
+    #[account(constraint = borrower.key() == vault_state.borrower @ ErrorCode::Unauthorized)]
+    pub borrower: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = borrower_ata.owner == borrower.key(),
+        constraint = borrower_ata.mint == mint_account.key()
+    )]
+    pub borrower_ata: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct RepayAndRelease<'info> {
+    #[account(mut)]
+    pub vault_state: Account<'info, VaultState>,
+
+    /// CHECK: PDA signer for the collateral leg
+    #[account(
+        seeds = [SEED_AUTH, vault_state.key().as_ref()],
+        bump = vault_state.vault_authority_bump
+    )]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_account,
+        associated_token::authority = vault_authority,
+        associated_token::token_program = token_program
+    )]
+    pub vault_ata: InterfaceAccount<'info, TokenAccount>,
+
+    /// Basket mint returned on this repayment (checked against the basket in handler)
+    #[account(mint::token_program = token_program)]
+    pub mint_account: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        constraint = debt_mint_account.key() == vault_state.loan.debt_mint @ ErrorCode::Unauthorized,
+        mint::token_program = debt_token_program
+    )]
+    pub debt_mint_account: InterfaceAccount<'info, Mint>,
+
+    #[account(constraint = borrower.key() == vault_state.borrower @ ErrorCode::Unauthorized)]
+    pub borrower: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = borrower_debt_ata.owner == borrower.key(),
+        constraint = borrower_debt_ata.mint == debt_mint_account.key()
+    )]
+    pub borrower_debt_ata: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = borrower_collateral_ata.owner == borrower.key(),
+        constraint = borrower_collateral_ata.mint == mint_account.key()
+    )]
+    pub borrower_collateral_ata: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: lender owner for the ATA derivation; pinned to the vault's lender
+    #[account(constraint = lender.key() == vault_state.lender @ ErrorCode::Unauthorized)]
+    pub lender: UncheckedAccount<'info>,
+
+    /// Lender's debt-mint ATA receives the repayment
+    #[account(
+        mut,
+        associated_token::mint = debt_mint_account,
+        associated_token::authority = lender,
+        associated_token::token_program = debt_token_program
+    )]
+    pub lender_debt_ata: InterfaceAccount<'info, TokenAccount>,
+
+    /// Token programs owning the collateral and the debt mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub debt_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct MarkToMarket<'info> {
+    /// Pyth feeds of every funded basket asset are passed as remaining accounts
+    #[account(mut)]
+    pub vault_state: Account<'info, VaultState>,
+}
+
+#[derive(Accounts)]
+pub struct StartAuction<'info> {
+    #[account(mut)]
+    pub vault_state: Account<'info, VaultState>,
+
+    #[account(
+        init,
+        payer = starter,
+        space = 8 + Auction::SIZE,
+        seeds = [SEED_AUCTION, vault_state.key().as_ref()],
+        bump
+    )]
+    pub auction: Account<'info, Auction>,
+
+    /// CHECK: PDA owner of the proceeds ATA
+    #[account(
+        seeds = [SEED_AUTH, vault_state.key().as_ref()],
+        bump = vault_state.vault_authority_bump
+    )]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    /// Basket mint to auction (checked against the basket in handler)
+    pub mint_account: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        constraint = debt_mint_account.key() == vault_state.loan.debt_mint @ ErrorCode::Unauthorized,
+        mint::token_program = debt_token_program
+    )]
+    pub debt_mint_account: InterfaceAccount<'info, Mint>,
+
+    /// Escrow for bid proceeds = ATA(debt_mint, vault_authority)
+    #[account(
+        init_if_needed,
+        payer = starter,
+        associated_token::mint = debt_mint_account,
+        associated_token::authority = vault_authority,
+        associated_token::token_program = debt_token_program
+    )]
+    pub proceeds_ata: InterfaceAccount<'info, TokenAccount>,
+
+    /// Anyone; pays auction rent and gets it back on settle (the auctioned mint's Pyth feed
+    /// is passed as a remaining account)
+    #[account(mut)]
+    pub starter: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub debt_token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct Bid<'info> {
+    #[account(mut)]
+    pub vault_state: Account<'info, VaultState>,
+
+    #[account(
+        mut,
+        seeds = [SEED_AUCTION, vault_state.key().as_ref()],
+        bump = auction.bump
+    )]
+    pub auction: Account<'info, Auction>,
+
+    /// CHECK: PDA signer for vault transfers
+    #[account(
+        seeds = [SEED_AUTH, vault_state.key().as_ref()],
+        bump = vault_state.vault_authority_bump
+    )]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_account,
+        associated_token::authority = vault_authority,
+        associated_token::token_program = token_program
+    )]
+    pub vault_ata: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = mint_account.key() == auction.mint @ ErrorCode::Unauthorized,
+        mint::token_program = token_program
+    )]
+    pub mint_account: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        constraint = debt_mint_account.key() == vault_state.loan.debt_mint @ ErrorCode::Unauthorized,
+        mint::token_program = debt_token_program
+    )]
+    pub debt_mint_account: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        associated_token::mint = debt_mint_account,
+        associated_token::authority = vault_authority,
+        associated_token::token_program = debt_token_program
+    )]
+    pub proceeds_ata: InterfaceAccount<'info, TokenAccount>,
+
+    pub bidder: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = bidder_debt_ata.owner == bidder.key(),
+        constraint = bidder_debt_ata.mint == debt_mint_account.key()
+    )]
+    pub bidder_debt_ata: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = bidder_collateral_ata.mint == mint_account.key()
+    )]
+    pub bidder_collateral_ata: InterfaceAccount<'info, TokenAccount>,
+
+    /// Token programs owning the collateral and the debt mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub debt_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct SettleAuction<'info> {
+    #[account(mut)]
+    pub vault_state: Account<'info, VaultState>,
+
+    #[account(
+        mut,
+        close = starter,
+        seeds = [SEED_AUCTION, vault_state.key().as_ref()],
+        bump = auction.bump
+    )]
+    pub auction: Account<'info, Auction>,
+
+    /// CHECK: rent refund target; pinned to the auction starter
+    #[account(mut, constraint = starter.key() == auction.starter @ ErrorCode::Unauthorized)]
+    pub starter: UncheckedAccount<'info>,
+
+    /// CHECK: PDA signer for proceeds transfers
+    #[account(
+        seeds = [SEED_AUTH, vault_state.key().as_ref()],
+        bump = vault_state.vault_authority_bump
+    )]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    #[account(
+        constraint = debt_mint_account.key() == vault_state.loan.debt_mint @ ErrorCode::Unauthorized,
+        mint::token_program = debt_token_program
+    )]
+    pub debt_mint_account: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        associated_token::mint = debt_mint_account,
+        associated_token::authority = vault_authority,
+        associated_token::token_program = debt_token_program
+    )]
+    pub proceeds_ata: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = lender_debt_ata.owner == vault_state.lender @ ErrorCode::Unauthorized,
+        constraint = lender_debt_ata.mint == debt_mint_account.key()
+    )]
+    pub lender_debt_ata: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = custodian_debt_ata.owner == vault_state.custodian @ ErrorCode::Unauthorized,
+        constraint = custodian_debt_ata.mint == debt_mint_account.key()
+    )]
+    pub custodian_debt_ata: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = borrower_debt_ata.owner == vault_state.borrower @ ErrorCode::Unauthorized,
+        constraint = borrower_debt_ata.mint == debt_mint_account.key()
+    )]
+    pub borrower_debt_ata: InterfaceAccount<'info, TokenAccount>,
+
+    pub debt_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct SetEligibilitySchedule<'info> {
+    #[account(mut)]
+    pub vault_state: Account<'info, VaultState>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + EligibilitySchedule::SIZE,
+        seeds = [SEED_ELIGIBILITY, vault_state.key().as_ref()],
+        bump
+    )]
+    pub eligibility_schedule: Account<'info, EligibilitySchedule>,
+
+    pub custodian: Signer<'info>,
+    pub lender: Signer<'info>,
+
+    /// Pays for the schedule account on first use (can be any signer)
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PostManualPrice<'info> {
+    #[account(mut)]
+    pub vault_state: Account<'info, VaultState>,
+
+    #[account(
+        init_if_needed,
+        payer = custodian,
+        space = 8 + ManualPrice::SIZE,
+        seeds = [SEED_MANUAL_PRICE, vault_state.key().as_ref(), mint_account.key().as_ref()],
+        bump
+    )]
+    pub manual_price: Account<'info, ManualPrice>,
+
+    /// A basket mint, the debt mint (quote cross rate), or a mint of the eligibility schedule
+    pub mint_account: InterfaceAccount<'info, Mint>,
+
+    /// Needed only to price a schedule mint that has not joined the basket yet
+    #[account(
+        seeds = [SEED_ELIGIBILITY, vault_state.key().as_ref()],
+        bump = eligibility_schedule.bump
+    )]
+    pub eligibility_schedule: Option<Account<'info, EligibilitySchedule>>,
+
+    #[account(mut)]
+    pub custodian: Signer<'info>,
+
+    /// Optional co-signature; required for moves beyond `manual_max_change_bps`
+    pub lender: Option<Signer<'info>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RecordPriceObservation<'info> {
+    #[account(mut)]
+    pub vault_state: Account<'info, VaultState>,
+
+    #[account(
+        init_if_needed,
+        payer = cranker,
+        space = 8 + PriceObservations::SIZE,
+        seeds = [SEED_OBSERVATIONS, vault_state.key().as_ref(), mint_account.key().as_ref()],
+        bump
+    )]
+    pub price_observations: Account<'info, PriceObservations>,
+
+    pub mint_account: InterfaceAccount<'info, Mint>,
+
+    /// Anyone may crank; pays for the buffer on first use
+    #[account(mut)]
+    pub cranker: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ViewVault<'info> {
+    pub vault_state: Account<'info, VaultState>,
+}
+
+#[derive(Accounts)]
+pub struct AllRoles<'info> {
+    #[account(mut)]
+    pub vault_state: Account<'info, VaultState>,
+    pub custodian: Signer<'info>,
+    pub borrower: Signer<'info>,
+    pub lender: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ResetApprovals<'info> {
+    pub vault_state: Account<'info, VaultState>,
+
+    #[account(
+        mut,
+        seeds = [
+            SEED_RELEASE,
+            vault_state.key().as_ref(),
+            release_proposal.nonce.to_le_bytes().as_ref()
+        ],
+        bump = release_proposal.bump
+    )]
+    pub release_proposal: Account<'info, ReleaseProposal>,
+
+    pub custodian: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeGovernance<'info> {
+    #[account(mut)]
+    pub vault_state: Account<'info, VaultState>,
+
+    #[account(
+        init,
+        payer = proposer,
+        space = 8 + GovernanceProposal::SIZE,
+        seeds = [
+            SEED_GOVERNANCE,
+            vault_state.key().as_ref(),
+            vault_state.governance_nonce.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub governance_proposal: Account<'info, GovernanceProposal>,
+
+    /// Proposer must be one of the three roles; pays proposal rent
+    #[account(mut)]
+    pub proposer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveGovernance<'info> {
+    pub vault_state: Account<'info, VaultState>,
+
+    #[account(
+        mut,
+        seeds = [
+            SEED_GOVERNANCE,
+            vault_state.key().as_ref(),
+            governance_proposal.nonce.to_le_bytes().as_ref()
+        ],
+        bump = governance_proposal.bump
+    )]
+    pub governance_proposal: Account<'info, GovernanceProposal>,
+
+    /// Any of the three role signers
+    pub role_signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelGovernance<'info> {
+    pub vault_state: Account<'info, VaultState>,
+
+    #[account(
+        mut,
+        close = proposer,
+        seeds = [
+            SEED_GOVERNANCE,
+            vault_state.key().as_ref(),
+            governance_proposal.nonce.to_le_bytes().as_ref()
+        ],
+        bump = governance_proposal.bump
+    )]
+    pub governance_proposal: Account<'info, GovernanceProposal>,
+
+    /// CHECK: rent refund target; pinned to the proposal's proposer
+    #[account(mut, constraint = proposer.key() == governance_proposal.proposer @ ErrorCode::Unauthorized)]
+    pub proposer: UncheckedAccount<'info>,
+
+    /// Proposer, any role once the proposal has expired, or a vetoing role
+    pub role_signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteGovernance<'info> {
+    #[account(mut)]
+    pub vault_state: Account<'info, VaultState>,
+
+    /// Approved proposal; closed to its proposer once executed
+    #[account(
+        mut,
+        close = proposer,
+        seeds = [
+            SEED_GOVERNANCE,
+            vault_state.key().as_ref(),
+            governance_proposal.nonce.to_le_bytes().as_ref()
+        ],
+        bump = governance_proposal.bump
+    )]
+    pub governance_proposal: Account<'info, GovernanceProposal>,
+
+    /// CHECK: rent refund target; pinned to the proposal's proposer
+    #[account(mut, constraint = proposer.key() == governance_proposal.proposer @ ErrorCode::Unauthorized)]
+    pub proposer: UncheckedAccount<'info>,
+
+    /// Any of the three role signers
+    pub role_signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptRole<'info> {
+    #[account(mut)]
+    pub vault_state: Account<'info, VaultState>,
+
+    /// Approved `RotateRole` proposal; closed to its proposer once accepted
+    #[account(
+        mut,
+        close = proposer,
+        seeds = [
+            SEED_GOVERNANCE,
+            vault_state.key().as_ref(),
+            governance_proposal.nonce.to_le_bytes().as_ref()
+        ],
+        bump = governance_proposal.bump
+    )]
+    pub governance_proposal: Account<'info, GovernanceProposal>,
+
+    /// CHECK: rent refund target; pinned to the proposal's proposer
+    #[account(mut, constraint = proposer.key() == governance_proposal.proposer @ ErrorCode::Unauthorized)]
+    pub proposer: UncheckedAccount<'info>,
+
+    /// Incoming role holder named in the proposal
+    pub new_holder: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseVault<'info> {
+    /// Seeds are not re-derived: role rotation changes the keys the PDA was created with.
+    #[account(mut, close = rent_payer)]
+    pub vault_state: Account<'info, VaultState>,
+
+    /// Approved `CloseVault` proposal; closed to its proposer
+    #[account(
+        mut,
+        close = proposer,
+        seeds = [
+            SEED_GOVERNANCE,
+            vault_state.key().as_ref(),
+            governance_proposal.nonce.to_le_bytes().as_ref()
+        ],
+        bump = governance_proposal.bump
+    )]
+    pub governance_proposal: Account<'info, GovernanceProposal>,
+
+    /// CHECK: rent refund target; pinned to the proposal's proposer
+    #[account(mut, constraint = proposer.key() == governance_proposal.proposer @ ErrorCode::Unauthorized)]
+    pub proposer: UncheckedAccount<'info>,
+
+    /// CHECK: rent refund target for vault state and vault ATAs; pinned to the init payer
+    #[account(mut, constraint = rent_payer.key() == vault_state.rent_payer @ ErrorCode::Unauthorized)]
+    pub rent_payer: UncheckedAccount<'info>,
+
+    /// CHECK: PDA signer for the dust sweeps and ATA closes
+    #[account(
+        seeds = [SEED_AUTH, vault_state.key().as_ref()],
+        bump = vault_state.vault_authority_bump
+    )]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    /// Any of the three role signers
+    pub role_signer: Signer<'info>,
+}
+
+/* --------------------------------- State ---------------------------------- */
+
+#[account]
+pub struct VaultState {
+    pub basket: Vec<CollateralAsset>, // collateral mints; [0] is the mint the PDA was seeded with
+    pub vault_authority_bump: u8,
+    pub custodian: Pubkey,
+    pub borrower: Pubkey,
+    pub lender: Pubkey,
+    pub is_frozen: bool,
+    pub frozen_at: i64,                // when the current freeze began (0 = not frozen)
+    pub release_nonce: u64,            // next ReleaseProposal nonce
+    pub open_release_proposals: u16,   // proposals not yet executed/cancelled
+    pub open_aux_accounts: u16,        // schedule, ManualPrice and PriceObservations PDAs
+    pub executor: Pubkey,              // optional non-role release executor (default = none)
+    pub governance_nonce: u64,         // next GovernanceProposal nonce
+    pub roles_epoch: u64,              // bumped on every role rotation; stales older proposals
+    pub quote_epoch: u64,              // bumped on every quote change; stales TWAP observations
+    pub rent_payer: Pubkey,            // initialization payer; receives rent on close
+    pub accepted_mint_risks: u8,       // MINT_RISK_* bits all parties accepted at init
+
+    // Governance & safety
+    pub threshold: u8,        // default 2 (2-of-3)
+    pub quorum: QuorumPolicy, // per-action overrides of `threshold`
+    pub risk_timelock_secs: i64, // delay on risk/oracle governance changes
+    pub last_cap_reset_ts: i64,
+    pub released_today: u64,          // token units (fallback)
+    pub released_today_quote_1e6: u64, // quote 1e6 when Pyth enabled
+
+    pub price_config: PriceConfig,
+    pub loan: LoanTerms,
+
+    // Margin
+    pub status: VaultStatus,
+    pub margin_call_deadline: i64, // cure deadline while in MarginCall
+}
+
+/// One basket mint; its vault ATA is ATA(mint, vault_authority).
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct CollateralAsset {
+    pub mint: Pubkey,
+    pub decimals: u8,
+    pub token_program: Pubkey, // SPL Token or Token-2022, the mint's owner
+    pub ui_scaled: bool,    // interest-bearing or scaled UI amount mint (value = UI amount)
+    pub price_feed: Pubkey, // Pyth push price account (USD quote)
+    pub feed_id: [u8; 32],  // Pyth pull feed id (USD quote)
+    pub switchboard_feed: Pubkey, // Switchboard On-Demand pull feed (USD quote)
+    pub haircut_bps: u16,   // lending value = market value * (1 - haircut)
+    pub amount_locked: u64,
+    pub last_price: i64,    // last price accepted for a withdrawal (price-move breaker)
+    pub last_expo: i32,
+    pub last_price_ts: i64, // 0 = no reference price
+}
+
+impl CollateralAsset {
+    pub const SIZE: usize =
+        32 + // mint
+        1  + // decimals
+        32 + // token_program
+        1  + // ui_scaled
+        32 + // price_feed
+        32 + // feed_id
+        32 + // switchboard_feed
+        2  + // haircut_bps
+        8  + // amount_locked
+        8  + // last_price
+        4  + // last_expo
+        8;   // last_price_ts
+}
+
+/// Collateral eligibility schedule agreed by custodian and lender.
+#[account]
+pub struct EligibilitySchedule {
+    pub vault: Pubkey,
+    pub entries: Vec<EligibleCollateral>,
+    pub updated_at: i64,
+    pub bump: u8,
+}
+
+impl EligibilitySchedule {
+    pub const SIZE: usize =
+        32 + // vault
+        4 + MAX_BASKET_ASSETS * EligibleCollateral::SIZE + // entries
+        8  + // updated_at
+        1;   // bump
+
+    pub fn entry(&self, mint: Pubkey) -> Option<&EligibleCollateral> {
+        self.entries.iter().find(|e| e.mint == mint)
+    }
+}
+
+/// One eligible mint and the limits that apply to it.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct EligibleCollateral {
+    pub mint: Pubkey,
+    pub price_feed: Pubkey, // used when `PriceConfig::oracle_sources` lists PythPush
+    pub feed_id: [u8; 32],  // used for PythPull
+    pub switchboard_feed: Pubkey, // used for SwitchboardOnDemand
+    pub haircut_bps: u16,
+    pub max_concentration_bps: u16, // max share of basket market value
+    pub max_conf_bps: u16,          // minimum oracle confidence: conf / price ceiling
+}
+
+impl EligibleCollateral {
+    pub const SIZE: usize =
+        32 + // mint
+        32 + // price_feed
+        32 + // feed_id
+        32 + // switchboard_feed
+        2  + // haircut_bps
+        2  + // max_concentration_bps
+        2;   // max_conf_bps
+}
+
+/// Custodian-attested USD price of one mint, per vault.
+#[account]
+pub struct ManualPrice {
+    pub vault: Pubkey,
+    pub mint: Pubkey,
+    pub price: i64,
+    pub expo: i32,
+    pub publish_time: i64,
+    pub lender_cosigned: bool, // lender co-signed the latest update
+    pub ref_price: i64,        // reference for `manual_max_change_bps` (0 = none yet)
+    pub ref_expo: i32,
+    pub ref_ts: i64,           // set by a lender co-sign or at the start of a change window
+    pub bump: u8,
+}
+
+impl ManualPrice {
+    pub const SIZE: usize =
+        32 + // vault
+        32 + // mint
+        8  + // price
+        4  + // expo
+        8  + // publish_time
+        1  + // lender_cosigned
+        8  + // ref_price
+        4  + // ref_expo
+        8  + // ref_ts
+        1;   // bump
+
+    /// Record a new price. Without the lender's co-signature it may move at most
+    /// `manual_max_change_bps` from the reference, which a new `manual_change_window_secs`
+    /// re-anchors to the latest price; the first and every co-signed price become it.
+    pub fn post(
+        &mut self,
+        cfg: &PriceConfig,
+        price: i64,
+        expo: i32,
+        lender_cosigned: bool,
+        now: i64,
+    ) -> Result<()> {
+        if self.price > 0 && now >= self.ref_ts.saturating_add(cfg.manual_change_window_secs) {
+            self.ref_price = self.price;
+            self.ref_expo = self.expo;
+            self.ref_ts = now;
+        }
+        if self.ref_price > 0 && !lender_cosigned {
+            let common = expo.min(self.ref_expo);
+            let reference = scale_to_expo(self.ref_price, self.ref_expo, common)?;
+            let moved = scale_to_expo(price, expo, common)?.abs_diff(reference);
+            require!(
+                moved.checked_mul(10_000).ok_or(ErrorCode::MathOverflow)?
+                    <= reference
+                        .checked_mul(cfg.manual_max_change_bps as u128)
+                        .ok_or(ErrorCode::MathOverflow)?,
+                ErrorCode::ManualPriceChangeTooLarge
+            );
+        }
+        if self.ref_price == 0 || lender_cosigned {
+            self.ref_price = price;
+            self.ref_expo = expo;
+            self.ref_ts = now;
+        }
+        self.price = price;
+        self.expo = expo;
+        self.publish_time = now;
+        self.lender_cosigned = lender_cosigned;
+        Ok(())
+    }
+}
+
+/// TWAP ring buffer of aggregated spot prices for one basket mint.
+#[account]
+pub struct PriceObservations {
+    pub vault: Pubkey,
+    pub mint: Pubkey,
+    pub quote_epoch: u64, // `VaultState::quote_epoch` the observations are quoted in
+    pub head: u8, // next slot to write
+    pub len: u8,  // observations held, up to TWAP_CAPACITY
+    pub observations: [PriceObservation; TWAP_CAPACITY],
+    pub bump: u8,
+}
+
+impl PriceObservations {
+    pub const SIZE: usize =
+        32 + // vault
+        32 + // mint
+        8  + // quote_epoch
+        1  + // head
+        1  + // len
+        TWAP_CAPACITY * PriceObservation::SIZE + // observations
+        1;   // bump
+
+    /// Drop every observation, e.g. after the quote currency changed.
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    pub fn push(&mut self, obs: PriceObservation) {
+        self.observations[self.head as usize] = obs;
+        self.head = ((self.head as usize + 1) % TWAP_CAPACITY) as u8;
+        self.len = (self.len as usize + 1).min(TWAP_CAPACITY) as u8;
+    }
+
+    /// Held observations, oldest first.
+    pub fn chronological(&self) -> impl Iterator<Item = &PriceObservation> {
+        let start = (self.head as usize + TWAP_CAPACITY - self.len as usize) % TWAP_CAPACITY;
+        (0..self.len as usize).map(move |i| &self.observations[(start + i) % TWAP_CAPACITY])
+    }
+
+    pub fn newest(&self) -> Option<&PriceObservation> {
+        self.chronological().last()
+    }
+}
+
+/// One TWAP observation, price and conf at `TWAP_EXPO`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct PriceObservation {
+    pub ts: i64,
+    pub price: i64,
+    pub conf: u64,
+}
+
+impl PriceObservation {
+    pub const SIZE: usize =
+        8 + // ts
+        8 + // price
+        8;  // conf
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct PriceConfig {
+    pub enabled: bool,
+    pub max_ltv_bps: u16,                 // e.g. 7000 = 70% LTV
+    pub max_single_release_quote_1e6: u64, // per-tx cap, quote 1e6
+    pub daily_cap_quote_1e6: u64,         // daily cap, quote 1e6
+    pub max_price_staleness_secs: i64,    // price freshness window
+    pub maintenance_ratio_bps: u16,       // margin call below this collateral/debt ratio
+    pub margin_cure_secs: i64,            // time the borrower has to top up
+    pub auction_premium_bps: u16,         // auction start = oracle * (1 + premium)
+    pub auction_floor_bps: u16,           // auction floor = oracle * floor
+    pub auction_duration_secs: i64,       // linear decay from start to floor
+    pub liquidation_fee_bps: u16,         // custodian fee, as bps of the debt
+    pub oracle_sources: [OracleSource; MAX_ORACLE_SOURCES], // aggregated at the median
+    pub min_oracle_sources: u8,           // fresh, agreeing sources required
+    pub max_oracle_deviation_bps: u16,    // agreement band around the median
+    pub valuation_mode: ValuationMode,    // price used by the LTV guard and quote caps
+    pub twap_window_secs: i64,            // TWAP lookback
+    pub twap_min_interval_secs: i64,      // spacing between TWAP observations
+    pub max_conf_bps: u16,                // conf / price ceiling for withdrawals
+    pub max_price_move_bps: u16,          // circuit breaker: move vs last accepted price
+    pub price_move_window_secs: i64,      // ... within this long of it (0 = off)
+    pub quote: QuoteConfig,               // currency caps, LTV and debt are measured in
+    pub manual_max_staleness_secs: i64,   // freshness window of `ManualPrice` attestations
+    pub manual_max_change_bps: u16,       // largest manual move without the lender's co-sign
+    pub manual_change_window_secs: i64,   // how often that move's reference re-anchors
+    pub min_verification: VerificationLevel, // weakest accepted Pyth pull update
+}
+
+impl PriceConfig {
+    pub const SIZE: usize =
+        1  + // enabled
+        2  + // max_ltv_bps
+        8  + // max_single_release_quote_1e6
+        8  + // daily_cap_quote_1e6
+        8  + // max_price_staleness_secs
+        2  + // maintenance_ratio_bps
+        8  + // margin_cure_secs
+        2  + // auction_premium_bps
+        2  + // auction_floor_bps
+        8  + // auction_duration_secs
+        2  + // liquidation_fee_bps
+        MAX_ORACLE_SOURCES + // oracle_sources
+        1  + // min_oracle_sources
+        2  + // max_oracle_deviation_bps
+        1  + // valuation_mode
+        8  + // twap_window_secs
+        8  + // twap_min_interval_secs
+        2  + // max_conf_bps
+        2  + // max_price_move_bps
+        8  + // price_move_window_secs
+        QuoteConfig::SIZE + // quote
+        8  + // manual_max_staleness_secs
+        2  + // manual_max_change_bps
+        8  + // manual_change_window_secs
+        2;   // min_verification
+}
+
+/// One oracle a basket price can be read from.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum OracleSource {
+    PythPush, // legacy price accounts, pinned by `CollateralAsset::price_feed`
+    PythPull, // pyth-solana-receiver `PriceUpdateV2`, pinned by `CollateralAsset::feed_id`
+    SwitchboardOnDemand, // pull feed, pinned by `CollateralAsset::switchboard_feed`
+    Manual,   // custodian-posted `ManualPrice` for (vault, mint)
+    None,     // unused slot
+}
+
+/// Quote currency: the loan's debt currency, held at par by the debt mint. Feeds quote USD;
+/// for any other currency a quote/USD cross feed converts them.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub struct QuoteConfig {
+    pub symbol: [u8; 8],          // e.g. "USD", "EUR" (zero-padded, informational)
+    pub cross_rate: bool,         // divide USD prices by the quote/USD feed below
+    pub price_feed: Pubkey,       // quote/USD Pyth push account
+    pub feed_id: [u8; 32],        // quote/USD Pyth pull feed id
+    pub switchboard_feed: Pubkey, // quote/USD Switchboard On-Demand feed
+}
+
+impl QuoteConfig {
+    pub const SIZE: usize =
+        8  + // symbol
+        1  + // cross_rate
+        32 + // price_feed
+        32 + // feed_id
+        32;  // switchboard_feed
+}
+
+/// Price the LTV guard and quote caps value collateral at.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ValuationMode {
+    Spot,       // aggregated spot
+    Ema,        // aggregated Pyth EMA (sources without one contribute spot)
+    MinSpotEma, // lower of spot and EMA, compared at price - conf
+    Twap,       // time-weighted average of the `PriceObservations` buffer
+}
+
+/// Wormhole verification of a pulled update (same layout as the receiver's enum);
+/// `Full` outranks any `Partial`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum VerificationLevel {
+    Partial { num_signatures: u8 },
+    Full,
+}
+
+impl VerificationLevel {
+    pub fn gte(&self, other: VerificationLevel) -> bool {
+        match (*self, other) {
+            (VerificationLevel::Full, _) => true,
+            (VerificationLevel::Partial { .. }, VerificationLevel::Full) => false,
+            (
+                VerificationLevel::Partial { num_signatures: a },
+                VerificationLevel::Partial { num_signatures: b },
+            ) => a >= b,
+        }
+    }
+}
+
+/// Mirror of pyth-solana-receiver's `PriceFeedMessage`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct PriceFeedMessage {
+    pub feed_id: [u8; 32],
+    pub price: i64,
+    pub conf: u64,
+    pub exponent: i32,
+    pub publish_time: i64,
+    pub prev_publish_time: i64,
+    pub ema_price: i64,
+    pub ema_conf: u64,
+}
+
+/// Mirror of pyth-solana-receiver's `PriceUpdateV2` account (data after the discriminator).
+/// Serializable so tests can build update accounts locally.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct PriceUpdateV2 {
+    pub write_authority: Pubkey,
+    pub verification_level: VerificationLevel,
+    pub price_message: PriceFeedMessage,
+    pub posted_slot: u64,
+}
+
+impl PriceUpdateV2 {
+    /// Decode a receiver-owned update account (owner and discriminator checked).
+    pub fn try_from_account(info: &AccountInfo) -> Result<Self> {
+        require_keys_eq!(*info.owner, PYTH_RECEIVER_PROGRAM_ID, ErrorCode::PriceAccountInvalid);
+        let data = info.try_borrow_data()?;
+        require!(
+            data.len() > 8 && data[..8] == PRICE_UPDATE_V2_DISCRIMINATOR,
+            ErrorCode::PriceAccountInvalid
+        );
+        PriceUpdateV2::deserialize(&mut &data[8..])
+            .map_err(|_| error!(ErrorCode::PriceAccountInvalid))
+    }
+}
+
+/// Margin lifecycle driven by `mark_to_market`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum VaultStatus {
+    Active,
+    MarginCall,
+    Default,
+    Liquidating, // Dutch auction running
+}
+
+/// Loan terms supplied at initialization.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct LoanParams {
+    pub debt_mint: Pubkey,
+    pub principal: u64, // debt-mint base units
+    pub apr_bps: u16,
+    pub maturity_ts: i64,
+}
+
+/// What the collateral secures; interest accrues lazily (simple, actual/365).
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct LoanTerms {
+    pub debt_mint: Pubkey,
+    pub debt_decimals: u8,
+    pub debt_token_program: Pubkey, // SPL Token or Token-2022, the debt mint's owner
+    pub principal: u64,        // outstanding principal (debt-mint base units)
+    pub apr_bps: u16,
+    pub start_ts: i64,
+    pub maturity_ts: i64,
+    pub accrued_interest: u64, // unpaid interest (debt-mint base units)
+    pub last_accrual_ts: i64,
+    pub accrual_carry: u64,    // sub-unit remainder, keeps repeated accruals exact
+}
+
+impl LoanTerms {
+    pub const SIZE: usize =
+        32 + // debt_mint
+        1  + // debt_decimals
+        32 + // debt_token_program
+        8  + // principal
+        2  + // apr_bps
+        8  + // start_ts
+        8  + // maturity_ts
+        8  + // accrued_interest
+        8  + // last_accrual_ts
+        8;   // accrual_carry
+
+    /// Accrue interest on outstanding principal from `last_accrual_ts` to `now`.
+    pub fn accrue(&mut self, now: i64) -> Result<()> {
+        if now <= self.last_accrual_ts {
+            return Ok(());
+        }
+        let dt = (now - self.last_accrual_ts) as u128;
+        let denom = 10_000u128 * SECS_PER_YEAR;
+        let num = (self.principal as u128)
+            .checked_mul(self.apr_bps as u128)
+            .and_then(|v| v.checked_mul(dt))
+            .and_then(|v| v.checked_add(self.accrual_carry as u128))
+            .ok_or(ErrorCode::MathOverflow)?;
+        let interest: u64 = (num / denom)
+            .try_into()
+            .map_err(|_| ErrorCode::MathOverflow)?;
+        self.accrual_carry = (num % denom) as u64; // < denom, fits
+        self.accrued_interest = self
+            .accrued_interest
+            .checked_add(interest)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.last_accrual_ts = now;
+        Ok(())
+    }
+
+    /// Book a payment against the loan: interest first, then principal.
+    /// Returns (interest_paid, principal_paid).
+    pub fn apply_payment(&mut self, amount: u64) -> Result<(u64, u64)> {
+        let interest_paid = amount.min(self.accrued_interest);
+        let principal_paid = amount - interest_paid;
+        self.accrued_interest -= interest_paid;
+        self.principal = self
+            .principal
+            .checked_sub(principal_paid)
+            .ok_or(ErrorCode::RepayExceedsDebt)?;
+        if self.principal == 0 {
+            // Nothing left to accrue on; drop the sub-unit remainder
+            self.accrual_carry = 0;
+        }
+        Ok((interest_paid, principal_paid))
+    }
+
+    /// Principal + accrued interest (as of the last accrual).
+    pub fn outstanding(&self) -> Result<u64> {
+        Ok(self
+            .principal
+            .checked_add(self.accrued_interest)
+            .ok_or(ErrorCode::MathOverflow)?)
+    }
+}
+
+/// Approval requirement for one kind of action.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QuorumRule {
+    pub min_approvals: u8, // 0 = inherit VaultState::threshold
+    pub required_mask: u8, // roles that must be among the approvers (same bit layout as approvals)
+}
+
+/// Which entry of the quorum table an action is checked against.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum QuorumAction {
+    Release,
+    RotateRole,
+    RiskParams,
+    Close,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct QuorumPolicy {
+    pub release: QuorumRule,
+    pub rotate_role: QuorumRule,
+    pub risk_params: QuorumRule,
+    pub close: QuorumRule,
+}
+
+impl QuorumPolicy {
+    pub const SIZE: usize = 4 * 2; // 4 x QuorumRule { u8, u8 }
+
+    pub fn rule(&self, action: QuorumAction) -> QuorumRule {
+        match action {
+            QuorumAction::Release => self.release,
+            QuorumAction::RotateRole => self.rotate_role,
+            QuorumAction::RiskParams => self.risk_params,
+            QuorumAction::Close => self.close,
+        }
+    }
+
+    pub fn rule_mut(&mut self, action: QuorumAction) -> &mut QuorumRule {
+        match action {
+            QuorumAction::Release => &mut self.release,
+            QuorumAction::RotateRole => &mut self.rotate_role,
+            QuorumAction::RiskParams => &mut self.risk_params,
+            QuorumAction::Close => &mut self.close,
+        }
+    }
+}
+
+impl VaultState {
+    pub const SIZE: usize =
+        4 + MAX_BASKET_ASSETS * CollateralAsset::SIZE + // basket
+        1  + // vault_authority_bump
+        32 + // custodian
+        32 + // borrower
+        32 + // lender
+        1  + // is_frozen
+        8  + // frozen_at
+        8  + // release_nonce
+        2  + // open_release_proposals
+        2  + // open_aux_accounts
+        32 + // executor
+        8  + // governance_nonce
+        8  + // roles_epoch
+        8  + // quote_epoch
+        32 + // rent_payer
+        1  + // accepted_mint_risks
+        1  + // threshold
+        QuorumPolicy::SIZE + // quorum
+        8  + // risk_timelock_secs
+        8  + // last_cap_reset_ts
+        8  + // released_today
+        8  + // released_today_quote_1e6
+        PriceConfig::SIZE + // price_config
+        LoanTerms::SIZE +   // loan
+        1  + // status
+        8;   // margin_call_deadline
+}
+
+/// One release request: approvals bind to exactly this amount, recipient and mint.
+#[account]
+pub struct ReleaseProposal {
+    pub vault: Pubkey,
+    pub nonce: u64,
+    pub proposer: Pubkey,
+    pub amount: u64,
+    pub net_of_fee: bool,  // `amount` is the recipient's net; gross up for the transfer fee
+    pub recipient: Pubkey, // recipient owner (ATA owner)
+    pub mint: Pubkey,
+    pub expires_at: i64,
+    pub roles_epoch: u64,
+    pub approvals_bitmap: u8, // bit0=custodian, bit1=borrower, bit2=lender
+    pub bump: u8,
+}
+
+impl ReleaseProposal {
+    pub const SIZE: usize =
+        32 + // vault
+        8  + // nonce
+        32 + // proposer
+        8  + // amount
+        1  + // net_of_fee
+        32 + // recipient
+        32 + // mint
+        8  + // expires_at
+        8  + // roles_epoch
+        1  + // approvals_bitmap
+        1;   // bump
+}
+
+/// Dutch auction of a defaulted vault's collateral; prices are quote 1e6 per whole token.
+#[account]
+pub struct Auction {
+    pub vault: Pubkey,
+    pub starter: Pubkey,
+    pub mint: Pubkey, // basket mint being sold
+    pub start_ts: i64,
+    pub end_ts: i64,
+    pub start_price_1e6: u64,
+    pub floor_price_1e6: u64,
+    pub collateral_for_sale: u64,
+    pub collateral_sold: u64,
+    pub debt_target: u64, // outstanding debt when the auction started
+    pub fee_target: u64,  // custodian liquidation fee
+    pub proceeds: u64,    // debt-mint units held in the proceeds ATA
+    pub bump: u8,
+}
+
+impl Auction {
+    pub const SIZE: usize =
+        32 + // vault
+        32 + // starter
+        32 + // mint
+        8  + // start_ts
+        8  + // end_ts
+        8  + // start_price_1e6
+        8  + // floor_price_1e6
+        8  + // collateral_for_sale
+        8  + // collateral_sold
+        8  + // debt_target
+        8  + // fee_target
+        8  + // proceeds
+        1;   // bump
+
+    /// Linear decay from start to floor over [start_ts, end_ts]; holds at floor afterwards.
+    pub fn current_price_1e6(&self, now: i64) -> Result<u64> {
+        if now >= self.end_ts {
+            return Ok(self.floor_price_1e6);
+        }
+        let elapsed = now.saturating_sub(self.start_ts) as u128;
+        let duration = (self.end_ts - self.start_ts) as u128;
+        let span = (self.start_price_1e6 - self.floor_price_1e6) as u128;
+        let drop = span.checked_mul(elapsed).ok_or(ErrorCode::MathOverflow)? / duration;
+        Ok(self.start_price_1e6 - drop as u64)
+    }
+}
+
+/// Vault-level change that needs approval from the current roles.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub enum GovernanceAction {
+    /// Hand `role` (0=custodian,1=borrower,2=lender) to `new_key`; applied by `accept_role`.
+    RotateRole { role: u8, new_key: Pubkey },
+    /// Change the default approval threshold (1..=3); needs unanimous consent.
+    SetThreshold { threshold: u8 },
+    /// Replace one entry of the per-action quorum table; needs unanimous consent.
+    SetQuorum { action: QuorumAction, rule: QuorumRule },
+    /// Replace the oracle/risk config; timelocked and vetoable.
+    SetPriceConfig { config: PriceConfig },
+    /// Change the delay applied to risk/oracle proposals; timelocked and vetoable.
+    SetRiskTimelock { secs: i64 },
+    /// Repoint a basket mint's oracle feeds and change its haircut; timelocked and vetoable.
+    SetAssetOracle {
+        mint: Pubkey,
+        price_feed: Pubkey,
+        feed_id: [u8; 32],
+        switchboard_feed: Pubkey,
+        haircut_bps: u16,
+    },
+    /// Close the vault; applied by `close_vault`. Unbooked dust is swept to `dust_recipient`
+    /// (`Pubkey::default()` = the vault ATA must already be empty).
+    CloseVault { dust_recipient: Pubkey },
+}
+
+impl GovernanceAction {
+    /// Largest serialized variant (tag + fields): SetPriceConfig
+    pub const MAX_SIZE: usize = 1 + PriceConfig::SIZE;
+}
+
+#[account]
+pub struct GovernanceProposal {
+    pub vault: Pubkey,
+    pub nonce: u64,
+    pub proposer: Pubkey,
+    pub action: GovernanceAction,
+    pub eta: i64, // earliest execution time (timelocked actions)
+    pub expires_at: i64,
+    pub roles_epoch: u64,
+    pub approvals_bitmap: u8, // bit0=custodian, bit1=borrower, bit2=lender
+    pub bump: u8,
+}
+
+impl GovernanceProposal {
+    pub const SIZE: usize =
+        32 + // vault
+        8  + // nonce
+        32 + // proposer
+        GovernanceAction::MAX_SIZE + // action
+        8  + // eta
+        8  + // expires_at
+        8  + // roles_epoch
+        1  + // approvals_bitmap
+        1;   // bump
+}
+
+/* -------------------------------- Events ---------------------------------- */
+
+#[event]
+pub struct VaultInitialized {
+    pub mint: Pubkey,
+    pub custodian: Pubkey,
+    pub borrower: Pubkey,
+    pub lender: Pubkey,
+}
+
+#[event]
+pub struct MintRiskAccepted {
+    pub mint: Pubkey,
+    pub risks: u8,    // MINT_RISK_* bits present on the mint
+    pub accepted: u8, // VaultState::accepted_mint_risks
+}
+
+#[event]
+pub struct LoanOpened {
+    pub debt_mint: Pubkey,
+    pub principal: u64,
+    pub apr_bps: u16,
+    pub start_ts: i64,
+    pub maturity_ts: i64,
+}
+
+#[event]
+pub struct LoanRepaid {
+    pub repay_amount: u64,
+    pub received: u64, // credited to the lender, net of any transfer fee; this is booked
+    pub interest_paid: u64,
+    pub principal_paid: u64,
+    pub collateral_mint: Pubkey,
+    pub collateral_released: u64,
+    pub remaining_collateral: u64, // of `collateral_mint`
+    pub outstanding_after: u64,
+}
+
+/// Valuation attached to margin events (all quote values at 1e6 scale).
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct HealthSnapshot {
+    pub collateral_quote_1e6: u64,
+    pub debt_quote_1e6: u64,
+    pub health_bps: u64, // collateral / debt
+}
+
+#[event]
+pub struct MarginCallIssued {
+    pub health: HealthSnapshot,
+    pub deadline: i64,
+}
+
+#[event]
+pub struct MarginCallCured {
+    pub health: HealthSnapshot,
+}
+
+#[event]
+pub struct VaultDefaulted {
+    pub health: HealthSnapshot,
+}
+
+#[event]
+pub struct AuctionStarted {
+    pub auction: Pubkey,
+    pub mint: Pubkey,
+    pub start_price_1e6: u64,
+    pub floor_price_1e6: u64,
+    pub end_ts: i64,
+    pub collateral_for_sale: u64,
+    pub debt_target: u64,
+    pub fee_target: u64,
+}
+
+#[event]
+pub struct AuctionBid {
+    pub auction: Pubkey,
+    pub bidder: Pubkey,
+    pub price_1e6: u64,
+    pub collateral_out: u64,
+    pub cost: u64,     // collateral_out at price_1e6
+    pub paid: u64,     // sent by the bidder: cost plus any debt-mint transfer fee
+    pub received: u64, // credited to the proceeds ATA and booked
+    pub proceeds: u64,
+}
+
+#[event]
+pub struct AuctionSettled {
+    pub auction: Pubkey,
+    pub collateral_sold: u64,
+    pub proceeds: u64,
+    pub to_lender: u64,
+    pub to_custodian: u64,
+    pub to_borrower: u64,
+    pub outstanding_after: u64,
+}
+
+#[event]
+pub struct CollateralDeposited {
+    pub mint: Pubkey,
+    pub amount: u64, // received by the vault ATA, net of `fee`
+    pub fee: u64,    // Token-2022 transfer fee withheld on the deposit
+    pub new_total: u64, // locked amount of `mint`
+}
+
+#[event]
+pub struct CollateralSubstituted {
+    pub mint_in: Pubkey,
+    pub amount_in: u64,
+    pub mint_out: Pubkey,
+    pub amount_out: u64,
+    pub value_before_quote_1e6: u64, // basket lending value
+    pub value_after_quote_1e6: u64,
+    pub lender_signed: bool,
+}
+
+#[event]
+pub struct EligibilityScheduleSet {
+    pub schedule: Pubkey,
+    pub entries: Vec<EligibleCollateral>,
+}
+
+#[event]
+pub struct CircuitBreakerTripped {
+    pub mint: Pubkey,
+    pub last_price: i64,
+    pub last_expo: i32,
+    pub last_price_ts: i64,
+    pub price: i64,
+    pub expo: i32,
+    pub publish_time: i64,
+    pub move_bps: u64,
+}
+
+#[event]
+pub struct PriceObserved {
+    pub observations: Pubkey,
+    pub mint: Pubkey,
+    pub price: i64,
+    pub conf: u64,
+    pub expo: i32,
+    pub ts: i64,
+}
+
+#[event]
+pub struct ManualPricePosted {
+    pub manual_price: Pubkey,
+    pub mint: Pubkey,
+    pub prev_price: i64, // 0 on the first post
+    pub prev_expo: i32,
+    pub ref_price: i64,  // reference the change limit is now measured from
+    pub ref_expo: i32,
+    pub price: i64,
+    pub expo: i32,
+    pub publish_time: i64,
+    pub posted_by: Pubkey,
+    pub lender_cosigned: bool,
+}
+
+#[event]
+pub struct ReleaseProposed {
+    pub proposal: Pubkey,
+    pub nonce: u64,
+    pub proposer: Pubkey,
+    pub amount: u64,
+    pub net_of_fee: bool,
+    pub recipient: Pubkey,
+    pub mint: Pubkey,
+    pub expires_at: i64,
+}
+
+#[event]
+pub struct ReleaseApproved {
+    pub proposal: Pubkey,
+    pub by_role: u8,
+    pub approvals_bitmap: u8,
+}
+
+#[event]
+pub struct ApprovalsReset {
+    pub proposal: Pubkey,
+    pub cleared_bitmap: u8, // approvals held before the reset
+    pub reset_by: Pubkey,
+}
+
+#[event]
+pub struct ReleaseCancelled {
+    pub proposal: Pubkey,
+    pub nonce: u64,
+    pub cancelled_by: Pubkey,
+}
+
+#[event]
+pub struct CollateralReleased {
+    pub proposal: Pubkey,
+    pub recipient: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64, // left the vault ATA; the recipient got `amount - fee`
+    pub fee: u64,    // Token-2022 transfer fee withheld from the recipient
+    pub remaining: u64, // locked amount of `mint`
+    pub executed_by: Pubkey,
+}
+
+#[event]
+pub struct ExcessWithdrawn {
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub value_quote_1e6: u64,       // market value (price - conf)
+    pub free_margin_quote_1e6: u64, // lending-value headroom before the withdrawal
+    pub remaining: u64,           // locked amount of `mint`
+}
+
+#[event]
+pub struct ExecutorSet {
+    pub old: Pubkey,
+    pub new: Pubkey,
+}
+
+#[event]
+pub struct Paused {}
+
+#[event]
+pub struct Unpaused {
+    pub frozen_secs: i64, // added to the margin-call deadline or the auction clock
+}
+
+#[event]
+pub struct StateSignal {
+    pub paused: bool,
+    pub open_release_proposals: u16,
+    pub basket_assets: u8,
+}
+
+#[event]
+pub struct GovernanceProposed {
+    pub proposal: Pubkey,
+    pub nonce: u64,
+    pub proposer: Pubkey,
+    pub action: GovernanceAction,
+    pub eta: i64,
+    pub expires_at: i64,
+}
+
+#[event]
+pub struct GovernanceApproved {
+    pub proposal: Pubkey,
+    pub by_role: u8,
+    pub approvals_bitmap: u8,
+}
+
+#[event]
+pub struct GovernanceCancelled {
+    pub proposal: Pubkey,
+    pub nonce: u64,
+    pub cancelled_by: Pubkey,
+}
+
+#[event]
+pub struct GovernanceVetoed {
+    pub proposal: Pubkey,
+    pub nonce: u64,
+    pub by_role: u8,
+}
+
+#[event]
+pub struct PriceConfigProposed {
+    pub proposal: Pubkey,
+    pub old: PriceConfig,
+    pub new: PriceConfig,
+    pub eta: i64,
+}
+
+#[event]
+pub struct PriceConfigChanged {
+    pub old: PriceConfig,
+    pub new: PriceConfig,
+}
+
+#[event]
+pub struct AssetOracleChanged {
+    pub mint: Pubkey,
+    pub old_price_feed: Pubkey,
+    pub old_feed_id: [u8; 32],
+    pub old_switchboard_feed: Pubkey,
+    pub old_haircut_bps: u16,
+    pub price_feed: Pubkey,
+    pub feed_id: [u8; 32],
+    pub switchboard_feed: Pubkey,
+    pub haircut_bps: u16,
+}
+
+#[event]
+pub struct RiskTimelockChanged {
+    pub old: i64,
+    pub new: i64,
+}
+
+#[event]
+pub struct GovernanceExecuted {
+    pub proposal: Pubkey,
+    pub action: GovernanceAction,
+    pub executed_by: Pubkey,
+}
+
+#[event]
+pub struct ThresholdChanged {
+    pub old: u8,
+    pub new: u8,
+}
+
+#[event]
+pub struct QuorumRuleChanged {
+    pub action: QuorumAction,
+    pub old: QuorumRule,
+    pub new: QuorumRule,
+}
+
+#[event]
+pub struct RoleRotated {
+    pub proposal: Pubkey,
+    pub role: u8,
+    pub old_holder: Pubkey,
+    pub new_holder: Pubkey,
+    pub roles_epoch: u64,
+    pub at: i64,
+}
+
+#[event]
+pub struct VaultAtaClosed {
+    pub mint: Pubkey, // one event per vault ATA closed (basket mints, then the proceeds ATA)
+    pub dust_recipient: Pubkey,
+    pub dust_amount: u64,
+}
+
+#[event]
+pub struct VaultClosed {
+    pub proposal: Pubkey,
+    pub rent_payer: Pubkey,
+    pub dust_recipient: Pubkey,
+    pub aux_closed: u16, // schedule, manual price and observation PDAs closed
+    pub closed_by: Pubkey,
+}
+
+#[event]
+pub struct PriceUsed {
+    pub source: OracleSource,
+    pub feed: Pubkey,
+    pub price: i64,
+    pub conf: u64,
+    pub expo: i32,
+    pub publish_time: i64,
+}
+
+/* ------------------------------- Error Codes ------------------------------ */
+
+#[error_code]
+pub enum ErrorCode {
+    #[msg("Invalid role")]
+    InvalidRole,
+    #[msg("Roles must be distinct")]
+    RoleNotDistinct,
+    #[msg("This action is unauthorized")]
+    Unauthorized,
+    #[msg("Not enough approvals (need at least threshold)")]
+    NotEnoughApprovals,
+    #[msg("Vault is paused")]
+    Paused,
+    #[msg("Release amount exceeds locked total or cap")]
+    AmountExceedsLocked,
+    #[msg("Math overflow")]
+    MathOverflow,
+    #[msg("Invalid amount")]
+    InvalidAmount,
+    #[msg("Internal: bump not found")]
+    BumpNotFound,
+    #[msg("Per-day cap exceeded")]
+    DailyCapExceeded,
+    #[msg("A release proposal is open")]
+    PendingReleaseFlow,
+    #[msg("Pyth price account invalid")]
+    PriceAccountInvalid,
+    #[msg("Price is stale")]
+    PriceStale,
+    #[msg("Non-positive price")]
+    PriceNonPositive,
+    #[msg("Quote-currency cap exceeded")]
+    QuoteCapExceeded,
+    #[msg("LTV check failed")]
+    LtvBreach,
+    #[msg("Invalid risk parameters")]
+    InvalidRiskParams,
+    #[msg("Proposal has expired")]
+    ProposalExpired,
+    #[msg("Proposal expiry must be in the future and within the max TTL")]
+    InvalidExpiry,
+    #[msg("Accounts do not match the approved proposal")]
+    ProposalMismatch,
+    #[msg("Release must be signed by a role or the configured executor")]
+    UnauthorizedExecutor,
+    #[msg("Proposal was made before a role rotation")]
+    StaleProposal,
+    #[msg("Threshold must be between 1 and 3")]
+    InvalidThreshold,
+    #[msg("A required approver has not approved")]
+    RequiredApproverMissing,
+    #[msg("Proposal action not valid for this instruction")]
+    WrongProposalAction,
+    #[msg("Timelock has not elapsed")]
+    TimelockActive,
+    #[msg("Veto window has closed")]
+    VetoWindowClosed,
+    #[msg("Vault still holds collateral")]
+    VaultNotEmpty,
+    #[msg("Invalid loan terms")]
+    InvalidLoanTerms,
+    #[msg("Repayment exceeds outstanding debt")]
+    RepayExceedsDebt,
+    #[msg("Price checks are disabled")]
+    PriceChecksDisabled,
+    #[msg("Vault is not in default")]
+    NotInDefault,
+    #[msg("Collateral is being auctioned")]
+    AuctionActive,
+    #[msg("Auction price is above the bidder's limit")]
+    AuctionPriceAboveLimit,
+    #[msg("Auction cannot be settled yet")]
+    AuctionNotFinished,
+    #[msg("Mint is not in the collateral basket")]
+    MintNotInBasket,
+    #[msg("Collateral basket is full")]
+    BasketFull,
+    #[msg("Mint is not in the eligibility schedule")]
+    MintNotEligible,
+    #[msg("Deposit breaks the mint's concentration limit")]
+    ConcentrationLimitExceeded,
+    #[msg("Oracle confidence interval too wide")]
+    PriceConfidenceTooWide,
+    #[msg("Substitution lowers the basket's collateral value")]
+    SubstitutionValueShortfall,
+    #[msg("Outside the eligibility schedule: the lender must co-sign")]
+    LenderSignOffRequired,
+    #[msg("Withdrawal exceeds the free margin")]
+    NoExcessCollateral,
+    #[msg("Price update verification level too low")]
+    InsufficientVerification,
+    #[msg("Not enough fresh oracle sources")]
+    OracleQuorumNotMet,
+    #[msg("Oracle sources disagree beyond the deviation band")]
+    OracleDeviation,
+    #[msg("Price observation recorded too soon after the last one")]
+    ObservationTooSoon,
+    #[msg("Not enough price observations in the TWAP window")]
+    TwapUnavailable,
+    #[msg("Manual price moved too far without the lender's co-signature")]
+    ManualPriceChangeTooLarge,
+    #[msg("Collateral mint has a freeze authority or extension the parties did not accept")]
+    MintPolicyViolation,
+    #[msg("Mint risk override has unknown bits or lacks the custodian's signature")]
+    InvalidMintRiskOverride,
+    #[msg("Feeds or haircut of a funded basket mint change only through governance")]
+    AssetOracleChangeNeedsGovernance,
+    #[msg("Mint account of an interest-bearing or scaled UI amount asset not passed")]
+    MintAccountMissing,
+    #[msg("Mint's interest-bearing or scaled UI amount config is malformed")]
+    MintUiAmountInvalid,
+    #[msg("Loan principal or interest is still outstanding")]
+    LoanOutstanding,
+    #[msg("Auction account of a liquidating vault not passed")]
+    AuctionAccountMissing,
+}
+
+/* ------------------------------- Utilities -------------------------------- */
+
+#[inline]
+fn has_bit(bitmap: u8, idx: u8) -> bool {
+    ((bitmap >> idx) & 1) == 1
+}
+
+#[inline]
+fn set_bit(bitmap: &mut u8, idx: u8) {
+    *bitmap |= 1 << idx;
+}
+
+#[inline]
+fn clear_bit(bitmap: &mut u8, idx: u8) {
+    *bitmap &= !(1 << idx);
+}
+
+#[inline]
+fn bitcount(bitmap: u8) -> u32 {
+    bitmap.count_ones()
+}
+
+#[inline]
+fn is_role(state: &VaultState, k: Pubkey) -> bool {
+    k == state.custodian || k == state.borrower || k == state.lender
+}
+
+/// Role index (0=custodian,1=borrower,2=lender) -> current holder.
+#[inline]
+fn role_key(state: &VaultState, role: u8) -> Result<Pubkey> {
+    match role {
+        0 => Ok(state.custodian),
+        1 => Ok(state.borrower),
+        2 => Ok(state.lender),
+        _ => err!(ErrorCode::InvalidRole),
+    }
+}
+
+/// New proposals must expire in the future and within `MAX_PROPOSAL_TTL_SECS`.
+#[inline]
+fn check_new_expiry(now: i64, expires_at: i64) -> Result<()> {
+    let max_expiry = now
+        .checked_add(MAX_PROPOSAL_TTL_SECS)
+        .ok_or(ErrorCode::MathOverflow)?;
+    require!(expires_at > now && expires_at <= max_expiry, ErrorCode::InvalidExpiry);
+    Ok(())
+}
+
+/// Static checks on a governance action against the current vault state.
+fn validate_governance_action(state: &VaultState, action: &GovernanceAction) -> Result<()> {
+    match *action {
+        GovernanceAction::RotateRole { role, new_key } => {
+            role_key(state, role)?;
+            require!(new_key != Pubkey::default(), ErrorCode::Unauthorized);
+            // Same distinctness rule as initialize_vault (also rejects a no-op rotation)
+            require!(!is_role(state, new_key), ErrorCode::RoleNotDistinct);
+        }
+        GovernanceAction::SetThreshold { threshold } => {
+            require!((1..=3).contains(&threshold), ErrorCode::InvalidThreshold);
+        }
+        GovernanceAction::SetQuorum { rule, .. } => {
+            require!(rule.min_approvals <= 3, ErrorCode::InvalidThreshold);
+            require!(rule.required_mask & !ALL_ROLES_MASK == 0, ErrorCode::InvalidRole);
+        }
+        GovernanceAction::SetPriceConfig { config } => validate_price_config(&config)?,
+        GovernanceAction::CloseVault { .. } => {}
+        GovernanceAction::SetRiskTimelock { secs } => {
+            require!(
+                (0..=MAX_RISK_TIMELOCK_SECS).contains(&secs),
+                ErrorCode::InvalidRiskParams
+            );
+        }
+        GovernanceAction::SetAssetOracle { mint, haircut_bps, .. } => {
+            asset_index(state, mint)?;
+            require!(haircut_bps < 10_000, ErrorCode::InvalidRiskParams);
+        }
+    }
+    Ok(())
+}
+
+/// Sanity bounds on a proposed price config.
+fn validate_price_config(config: &PriceConfig) -> Result<()> {
+    require!(config.max_ltv_bps <= 9_999, ErrorCode::InvalidRiskParams); // disallow 100%+
+    require!(config.max_price_staleness_secs >= 0, ErrorCode::InvalidRiskParams);
+    require!(config.maintenance_ratio_bps >= 10_000, ErrorCode::InvalidRiskParams);
+    require!(config.margin_cure_secs >= 0, ErrorCode::InvalidRiskParams);
+    require!(config.auction_premium_bps <= 10_000, ErrorCode::InvalidRiskParams);
+    require!(config.auction_floor_bps <= 10_000, ErrorCode::InvalidRiskParams);
+    require!(config.auction_duration_secs > 0, ErrorCode::InvalidRiskParams);
+    require!(config.liquidation_fee_bps <= 2_000, ErrorCode::InvalidRiskParams);
+
+    // Oracle set: no duplicates, quorum a majority of the sources, band in range
+    let sources = &config.oracle_sources;
+    let configured = sources.iter().filter(|s| **s != OracleSource::None).count();
+    for (i, s) in sources.iter().enumerate() {
+        require!(
+            *s == OracleSource::None || !sources[..i].contains(s),
+            ErrorCode::InvalidRiskParams
+        );
+    }
+    require!(!config.enabled || configured > 0, ErrorCode::InvalidRiskParams);
+    let min_sources = config.min_oracle_sources as usize;
+    require!(
+        min_sources >= 1 && min_sources <= configured.max(1) && min_sources * 2 > configured,
+        ErrorCode::InvalidRiskParams
+    );
+    require!(config.max_oracle_deviation_bps <= 10_000, ErrorCode::InvalidRiskParams);
+
+    // TWAP: observations must fit the window and, when used, keep up with the staleness window
+    require!(config.twap_min_interval_secs > 0, ErrorCode::InvalidRiskParams);
+    require!(
+        config.twap_window_secs > config.twap_min_interval_secs,
+        ErrorCode::InvalidRiskParams
+    );
+    require!(
+        config.valuation_mode != ValuationMode::Twap
+            || config.twap_min_interval_secs < config.max_price_staleness_secs,
+        ErrorCode::InvalidRiskParams
+    );
+
+    // Withdrawal guards
+    require!(config.max_conf_bps <= 10_000, ErrorCode::InvalidRiskParams);
+    require!(config.max_price_move_bps > 0, ErrorCode::InvalidRiskParams);
+    require!(config.price_move_window_secs >= 0, ErrorCode::InvalidRiskParams);
+
+    // Manual attestations
+    require!(config.manual_max_staleness_secs > 0, ErrorCode::InvalidRiskParams);
+    require!(config.manual_max_change_bps > 0, ErrorCode::InvalidRiskParams);
+    require!(config.manual_change_window_secs > 0, ErrorCode::InvalidRiskParams);
+    Ok(())
+}
+
+/// Sanity bounds on an eligibility schedule.
+fn validate_eligibility(entries: &[EligibleCollateral]) -> Result<()> {
+    require!(entries.len() <= MAX_BASKET_ASSETS, ErrorCode::BasketFull);
+    for (i, e) in entries.iter().enumerate() {
+        require!(
+            !entries[..i].iter().any(|o| o.mint == e.mint),
+            ErrorCode::InvalidRiskParams
+        );
+        require!(e.haircut_bps < 10_000, ErrorCode::InvalidRiskParams);
+        require!(
+            (1..=10_000).contains(&e.max_concentration_bps),
+            ErrorCode::InvalidRiskParams
+        );
+        require!(e.max_conf_bps <= 10_000, ErrorCode::InvalidRiskParams);
+    }
+    Ok(())
+}
+
+/// Risk/oracle changes are subject to `VaultState::risk_timelock_secs`.
+#[inline]
+fn is_timelocked(action: &GovernanceAction) -> bool {
+    matches!(
+        action,
+        GovernanceAction::SetPriceConfig { .. }
+            | GovernanceAction::SetRiskTimelock { .. }
+            | GovernanceAction::SetAssetOracle { .. }
+    )
+}
+
+/// Approvals in `bitmap` must meet `rule`: at least `min_approvals` (or the vault threshold
+/// when 0) and every role in `required_mask`.
+fn check_rule(state: &VaultState, rule: QuorumRule, bitmap: u8) -> Result<()> {
+    let min = if rule.min_approvals == 0 {
+        state.threshold
+    } else {
+        rule.min_approvals
+    };
+    require!(bitcount(bitmap) >= min as u32, ErrorCode::NotEnoughApprovals);
+    require!(
+        bitmap & rule.required_mask == rule.required_mask,
+        ErrorCode::RequiredApproverMissing
+    );
+    Ok(())
+}
+
+/// Check `bitmap` against the quorum table entry for `action`.
+#[inline]
+fn require_quorum(state: &VaultState, action: QuorumAction, bitmap: u8) -> Result<()> {
+    check_rule(state, state.quorum.rule(action), bitmap)
+}
+
+/// Quorum for a governance proposal; threshold and quorum-table changes are unanimous.
+fn require_governance_quorum(state: &VaultState, proposal: &GovernanceProposal) -> Result<()> {
+    match proposal.action {
+        GovernanceAction::RotateRole { .. } => {
+            require_quorum(state, QuorumAction::RotateRole, proposal.approvals_bitmap)
+        }
+        GovernanceAction::SetPriceConfig { .. }
+        | GovernanceAction::SetRiskTimelock { .. }
+        | GovernanceAction::SetAssetOracle { .. } => {
+            require_quorum(state, QuorumAction::RiskParams, proposal.approvals_bitmap)
+        }
+        GovernanceAction::CloseVault { .. } => {
+            require_quorum(state, QuorumAction::Close, proposal.approvals_bitmap)
+        }
+        GovernanceAction::SetThreshold { .. } | GovernanceAction::SetQuorum { .. } => check_rule(
+            state,
+            QuorumRule {
+                min_approvals: 3,
+                required_mask: ALL_ROLES_MASK,
+            },
+            proposal.approvals_bitmap,
+        ),
+    }
+}
+
+/// Close every `EligibilitySchedule`, `ManualPrice` and `PriceObservations` of `vault` among
+/// `accounts` to `rent_payer`, the way a `close` constraint would. Accounts of other programs
+/// are skipped; one of ours belonging to another vault is rejected. Returns how many closed.
+fn close_aux_accounts<'a, 'info: 'a>(
+    vault: Pubkey,
+    accounts: impl Iterator<Item = &'a AccountInfo<'info>>,
+    rent_payer: &AccountInfo<'info>,
+) -> Result<u16> {
+    let mut closed: u16 = 0;
+    for info in accounts.filter(|a| *a.owner == crate::ID) {
+        let owner_vault = {
+            let data = info.try_borrow_data()?;
+            if let Ok(a) = EligibilitySchedule::try_deserialize(&mut &data[..]) {
+                a.vault
+            } else if let Ok(a) = ManualPrice::try_deserialize(&mut &data[..]) {
+                a.vault
+            } else if let Ok(a) = PriceObservations::try_deserialize(&mut &data[..]) {
+                a.vault
+            } else {
+                continue;
+            }
+        };
+        require_keys_eq!(owner_vault, vault, ErrorCode::Unauthorized);
+        // Drain the rent, hand the account back to the system program
+        let refunded = rent_payer
+            .lamports()
+            .checked_add(info.lamports())
+            .ok_or(ErrorCode::MathOverflow)?;
+        **rent_payer.try_borrow_mut_lamports()? = refunded;
+        **info.try_borrow_mut_lamports()? = 0;
+        info.assign(&System::id());
+        info.realloc(0, false)?;
+        closed = closed.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+    }
+    Ok(closed)
+}
+
+/// MINT_RISK_* bits for a collateral mint: a freeze authority (either token program), and the
+/// Token-2022 permanent delegate, default-frozen account state and non-transferable extensions.
+fn mint_risks(mint: &AccountInfo) -> Result<u8> {
+    let data = mint.try_borrow_data()?;
+    let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+    let mut risks = 0u8;
+    if state.base.freeze_authority.is_some() {
+        risks |= MINT_RISK_FREEZE_AUTHORITY;
+    }
+    if *mint.owner == spl_token_2022::ID {
+        if let Ok(ext) = state.get_extension::<PermanentDelegate>() {
+            if Option::<Pubkey>::from(ext.delegate).is_some() {
+                risks |= MINT_RISK_PERMANENT_DELEGATE;
+            }
+        }
+        if let Ok(ext) = state.get_extension::<DefaultAccountState>() {
+            if ext.state == AccountState::Frozen as u8 {
+                risks |= MINT_RISK_DEFAULT_FROZEN;
+            }
+        }
+        if state.get_extension::<NonTransferable>().is_ok() {
+            risks |= MINT_RISK_NON_TRANSFERABLE;
+        }
+    }
+    Ok(risks)
+}
+
+/// Refuse a collateral mint with risks the parties did not accept; flag accepted ones.
+fn check_mint_policy(accepted: u8, mint: &AccountInfo) -> Result<()> {
+    let risks = mint_risks(mint)?;
+    require!(risks & !accepted == 0, ErrorCode::MintPolicyViolation);
+    if risks != 0 {
+        emit!(MintRiskAccepted {
+            mint: mint.key(),
+            risks,
+            accepted,
+        });
+    }
+    Ok(())
+}
+
+/// The mint's `TransferFeeConfig`, if it is a Token-2022 mint with a transfer fee.
+fn transfer_fee_config(mint: &AccountInfo) -> Result<Option<TransferFeeConfig>> {
+    if *mint.owner != spl_token_2022::ID {
+        return Ok(None);
+    }
+    let data = mint.try_borrow_data()?;
+    let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+    Ok(state.get_extension::<TransferFeeConfig>().ok().copied())
+}
+
+/// Transfer fee withheld when `amount` of `mint` moves in `epoch` (0 without a fee).
+fn transfer_fee(mint: &AccountInfo, amount: u64, epoch: u64) -> Result<u64> {
+    match transfer_fee_config(mint)? {
+        Some(cfg) => Ok(cfg
+            .calculate_epoch_fee(epoch, amount)
+            .ok_or(ErrorCode::MathOverflow)?),
+        None => Ok(0),
+    }
+}
+
+/// Amount of `mint` to send in `epoch` so that `net` arrives after the transfer fee.
+fn gross_up_for_fee(mint: &AccountInfo, net: u64, epoch: u64) -> Result<u64> {
+    let fee = match transfer_fee_config(mint)? {
+        Some(cfg) => cfg
+            .calculate_inverse_epoch_fee(epoch, net)
+            .ok_or(ErrorCode::MathOverflow)?,
+        None => 0,
+    };
+    Ok(net.checked_add(fee).ok_or(ErrorCode::MathOverflow)?)
+}
+
+/// Value bytes of extension `ext_type` in a Token-2022 mint's TLV area (`None` for SPL Token
+/// mints and mints without it). For extension types the typed accessors don't know.
+fn mint_extension_bytes(data: &[u8], ext_type: u16) -> Option<&[u8]> {
+    if data.get(MINT_TLV_START - 1) != Some(&MINT_ACCOUNT_TYPE) {
+        return None;
+    }
+    let mut i = MINT_TLV_START;
+    while i + 4 <= data.len() {
+        let ty = u16::from_le_bytes([data[i], data[i + 1]]);
+        let len = u16::from_le_bytes([data[i + 2], data[i + 3]]) as usize;
+        if ty == 0 {
+            return None; // uninitialized: nothing follows
+        }
+        let value = data.get(i + 4..i + 4 + len)?;
+        if ty == ext_type {
+            return Some(value);
+        }
+        i += 4 + len;
+    }
+    None
+}
+
+/// Whether the mint's UI amount differs from its raw amount over time (interest-bearing or
+/// scaled UI amount extension).
+fn mint_ui_scaled(mint: &AccountInfo) -> Result<bool> {
+    if *mint.owner != spl_token_2022::ID {
+        return Ok(false);
+    }
+    let data = mint.try_borrow_data()?;
+    Ok(interest_bearing_config(&data).is_some()
+        || mint_extension_bytes(&data, EXT_SCALED_UI_AMOUNT).is_some())
+}
+
+/// The mint's `InterestBearingConfig`, if it has one.
+fn interest_bearing_config(data: &[u8]) -> Option<InterestBearingConfig> {
+    let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(data).ok()?;
+    state.get_extension::<InterestBearingConfig>().ok().copied()
+}
+
+/// UI amount per raw amount at `now`, as the token program computes it in f64 (decimals
+/// excluded): continuous compounding of the interest-bearing rates, and the scaled UI amount
+/// multiplier (the new one once its effective timestamp has passed).
+fn ui_multiplier(mint: &AccountInfo, now: i64) -> Result<f64> {
+    let data = mint.try_borrow_data()?;
+    let i64_at = |b: &[u8], o: usize| i64::from_le_bytes(b[o..o + 8].try_into().unwrap());
+    let f64_at = |b: &[u8], o: usize| f64::from_le_bytes(b[o..o + 8].try_into().unwrap());
+    let mut m = 1f64;
+    if let Some(cfg) = interest_bearing_config(&data) {
+        let init_ts = i64::from(cfg.initialization_timestamp);
+        let avg_rate = i16::from(cfg.pre_update_average_rate) as f64;
+        let last_ts = i64::from(cfg.last_update_timestamp);
+        let rate = i16::from(cfg.current_rate) as f64;
+        let bps_secs = avg_rate * (last_ts - init_ts) as f64 + rate * (now - last_ts) as f64;
+        m *= (bps_secs / INTEREST_BEARING_SECS_PER_YEAR / 10_000.0).exp();
+    }
+    if let Some(b) = mint_extension_bytes(&data, EXT_SCALED_UI_AMOUNT) {
+        // authority(32) multiplier(f64) new_multiplier_effective_ts(i64) new_multiplier(f64)
+        require!(b.len() >= 56, ErrorCode::MintUiAmountInvalid);
+        m *= if now >= i64_at(b, 40) { f64_at(b, 48) } else { f64_at(b, 32) };
+    }
+    require!(m.is_finite() && m > 0.0, ErrorCode::MintUiAmountInvalid);
+    Ok(m)
+}
+
+/// `px` per raw unit of a UI-scaled asset, so raw amounts are valued at their UI amount at
+/// `now`: price rounded down, conf rounded up. Other assets pass through. The mint account is
+/// found by key among the instruction's named `mints`, then among `accounts`.
+fn ui_price<'info>(
+    asset: &CollateralAsset,
+    px: Price,
+    mints: &[AccountInfo<'info>],
+    accounts: &[AccountInfo<'info>],
+    now: i64,
+) -> Result<Price> {
+    if !asset.ui_scaled {
+        return Ok(px);
+    }
+    let mint = mints
+        .iter()
+        .chain(accounts)
+        .find(|a| a.key() == asset.mint)
+        .ok_or(ErrorCode::MintAccountMissing)?;
+    let m = (ui_multiplier(mint, now)? * UI_MULTIPLIER_SCALE) as i128;
+    let scale = UI_MULTIPLIER_SCALE as i128;
+    let price = (px.price as i128)
+        .checked_mul(m)
+        .ok_or(ErrorCode::MathOverflow)?
+        .div_euclid(scale);
+    let conf = (px.conf as i128)
+        .checked_mul(m)
+        .and_then(|v| v.checked_add(scale - 1))
+        .ok_or(ErrorCode::MathOverflow)?
+        / scale;
+    Ok(Price {
+        price: price.try_into().map_err(|_| error!(ErrorCode::MathOverflow))?,
+        conf: conf.try_into().map_err(|_| error!(ErrorCode::MathOverflow))?,
+        ..px
+    })
+}
+
+/// Basket slot holding `mint`.
+fn asset_index(state: &VaultState, mint: Pubkey) -> Result<usize> {
+    state
+        .basket
+        .iter()
+        .position(|a| a.mint == mint)
+        .ok_or_else(|| error!(ErrorCode::MintNotInBasket))
+}
+
+/// Basket slot for an eligible mint, adding it (with the schedule's feed and haircut) if new;
+/// a new mint must pass the vault's mint policy.
+fn basket_slot(
+    state: &mut VaultState,
+    rule: &EligibleCollateral,
+    mint: &InterfaceAccount<Mint>,
+) -> Result<usize> {
+    if let Some(idx) = state.basket.iter().position(|a| a.mint == rule.mint) {
+        return Ok(idx);
+    }
+    require!(state.basket.len() < MAX_BASKET_ASSETS, ErrorCode::BasketFull);
+    let mint_info = mint.to_account_info();
+    check_mint_policy(state.accepted_mint_risks, &mint_info)?;
+    state.basket.push(CollateralAsset {
+        mint: rule.mint,
+        decimals: mint.decimals,
+        token_program: *mint_info.owner,
+        ui_scaled: mint_ui_scaled(&mint_info)?,
+        price_feed: rule.price_feed,
+        feed_id: rule.feed_id,
+        switchboard_feed: rule.switchboard_feed,
+        haircut_bps: rule.haircut_bps,
+        amount_locked: 0,
+        last_price: 0,
+        last_expo: 0,
+        last_price_ts: 0,
+    });
+    Ok(state.basket.len() - 1)
+}
+
+/// Spot and EMA price of one feed; sources without an EMA repeat the spot price.
+#[derive(Clone, Copy)]
+struct Reading {
+    spot: Price,
+    ema: Price,
+}
+
+impl Reading {
+    fn spot_only(px: Price) -> Self {
+        Reading { spot: px, ema: px }
+    }
+}
+
+/// Load the Pyth feed `feed` (found by key among `accounts`) and return its spot and EMA
+/// prices, both no older than the configured staleness window.
+fn load_pyth_price(
+    cfg: &PriceConfig,
+    feed: Pubkey,
+    accounts: &[AccountInfo],
+    now: i64,
+) -> Result<Reading> {
+    require!(feed != Pubkey::default(), ErrorCode::PriceAccountInvalid);
+    let price_acc_info = accounts
+        .iter()
+        .find(|a| a.key() == feed)
+        .ok_or(ErrorCode::PriceAccountInvalid)?;
+
+    let feed = load_price_feed_from_account_info(price_acc_info)
+        .map_err(|_| ErrorCode::PriceAccountInvalid)?;
+
+    // ---- FIX: pass u64 staleness window to Pyth (0.8.0) ----
+    let spot = feed
+        .get_price_no_older_than(now, staleness_secs(cfg)?)
+        .ok_or_else(|| error!(ErrorCode::PriceStale))?;
+    let ema = feed
+        .get_ema_price_no_older_than(now, staleness_secs(cfg)?)
+        .ok_or_else(|| error!(ErrorCode::PriceStale))?;
+    Ok(Reading { spot, ema })
+}
+
+/// Load the freshest Pyth pull update for `feed_id` among `accounts` (any receiver-owned
+/// `PriceUpdateV2` qualifies; the feed id, not the address, is pinned) and check its
+/// verification level and age.
+fn load_pyth_pull_price(
+    cfg: &PriceConfig,
+    feed_id: &[u8; 32],
+    accounts: &[AccountInfo],
+    now: i64,
+) -> Result<Reading> {
+    require!(*feed_id != [0; 32], ErrorCode::PriceAccountInvalid);
+    let update = accounts
+        .iter()
+        .filter(|a| *a.owner == PYTH_RECEIVER_PROGRAM_ID)
+        .filter_map(|a| PriceUpdateV2::try_from_account(a).ok())
+        .filter(|u| u.price_message.feed_id == *feed_id)
+        .max_by_key(|u| u.price_message.publish_time)
+        .ok_or(ErrorCode::PriceAccountInvalid)?;
+    require!(
+        update.verification_level.gte(cfg.min_verification),
+        ErrorCode::InsufficientVerification
+    );
+
+    let msg = update.price_message;
+    require!(
+        msg.publish_time.saturating_add(staleness_secs(cfg)? as i64) >= now,
+        ErrorCode::PriceStale
+    );
+    Ok(Reading {
+        spot: Price {
+            price: msg.price,
+            conf: msg.conf,
+            expo: msg.exponent,
+            publish_time: msg.publish_time,
+        },
+        ema: Price {
+            price: msg.ema_price,
+            conf: msg.ema_conf,
+            expo: msg.exponent,
+            publish_time: msg.publish_time,
+        },
+    })
+}
+
+/// Load the Switchboard On-Demand pull feed `feed` (found by key among `accounts`). The
+/// 1e18 result is rescaled to `SB_EXPO` (price floored, std-dev rounded up as conf) and
+/// stamped with the timestamp of the submission it came from.
+fn load_switchboard_price(
+    cfg: &PriceConfig,
+    feed: Pubkey,
+    accounts: &[AccountInfo],
+    now: i64,
+) -> Result<Price> {
+    require!(feed != Pubkey::default(), ErrorCode::PriceAccountInvalid);
+    let info = accounts
+        .iter()
+        .find(|a| a.key() == feed)
+        .ok_or(ErrorCode::PriceAccountInvalid)?;
+    require_keys_eq!(*info.owner, SWITCHBOARD_ON_DEMAND_PROGRAM_ID, ErrorCode::PriceAccountInvalid);
+
+    let data = info.try_borrow_data()?;
+    require!(
+        data.len() >= 8 + SB_PULL_FEED_LEN && data[..8] == SB_PULL_FEED_DISCRIMINATOR,
+        ErrorCode::PriceAccountInvalid
+    );
+    let body = &data[8..];
+    let read_i128 = |off: usize| i128::from_le_bytes(body[off..off + 16].try_into().unwrap());
+    let read_u64 = |off: usize| u64::from_le_bytes(body[off..off + 8].try_into().unwrap());
+
+    // A feed that never produced a result reads as slot 0
+    require!(read_u64(SB_RESULT_SLOT_OFFSET) != 0, ErrorCode::PriceStale);
+    let idx = body[SB_RESULT_SUBMISSION_IDX_OFFSET] as usize % 32;
+    let publish_time = read_u64(SB_SUBMISSION_TS_OFFSET + 8 * idx) as i64;
+    require!(
+        publish_time.saturating_add(staleness_secs(cfg)? as i64) >= now,
+        ErrorCode::PriceStale
+    );
+
+    let value = read_i128(SB_RESULT_VALUE_OFFSET);
+    let std_dev = read_i128(SB_RESULT_STD_DEV_OFFSET).max(0);
+    let price: i64 = (value.div_euclid(SB_RESCALE))
+        .try_into()
+        .map_err(|_| error!(ErrorCode::MathOverflow))?;
+    let conf: u64 = ((std_dev + SB_RESCALE - 1) / SB_RESCALE)
+        .try_into()
+        .map_err(|_| error!(ErrorCode::MathOverflow))?;
+    Ok(Price {
+        price,
+        conf,
+        expo: SB_EXPO,
+        publish_time,
+    })
+}
+
+/// Load the custodian's `ManualPrice` for (`vault`, `mint`) among `accounts`, with its
+/// address; only accounts owned by this program and bound to this vault qualify. Its age is
+/// bounded by `manual_max_staleness_secs`, not the oracle staleness window.
+fn load_manual_price(
+    cfg: &PriceConfig,
+    vault: Pubkey,
+    mint: Pubkey,
+    accounts: &[AccountInfo],
+    now: i64,
+) -> Result<(Pubkey, Price)> {
+    let (key, manual) = accounts
+        .iter()
+        .filter(|a| *a.owner == crate::ID)
+        .filter_map(|a| {
+            let m = ManualPrice::try_deserialize(&mut &a.try_borrow_data().ok()?[..]).ok()?;
+            Some((a.key(), m))
+        })
+        .find(|(_, m)| m.vault == vault && m.mint == mint)
+        .ok_or(ErrorCode::PriceAccountInvalid)?;
+    require!(
+        manual.publish_time.saturating_add(cfg.manual_max_staleness_secs) >= now,
+        ErrorCode::PriceStale
+    );
+    Ok((
+        key,
+        Price {
+            price: manual.price,
+            conf: 0,
+            expo: manual.expo,
+            publish_time: manual.publish_time,
+        },
+    ))
+}
+
+/// Reading of one basket asset from a single oracle source, with the feed identifier
+/// reported in `PriceUsed` (account address, or the pull feed id).
+fn load_source_price(
+    cfg: &PriceConfig,
+    source: OracleSource,
+    vault: Pubkey,
+    asset: &CollateralAsset,
+    accounts: &[AccountInfo],
+    now: i64,
+) -> Result<(Pubkey, Reading)> {
+    match source {
+        OracleSource::PythPush => Ok((
+            asset.price_feed,
+            load_pyth_price(cfg, asset.price_feed, accounts, now)?,
+        )),
+        OracleSource::PythPull => Ok((
+            Pubkey::new_from_array(asset.feed_id),
+            load_pyth_pull_price(cfg, &asset.feed_id, accounts, now)?,
+        )),
+        OracleSource::SwitchboardOnDemand => Ok((
+            asset.switchboard_feed,
+            Reading::spot_only(load_switchboard_price(
+                cfg,
+                asset.switchboard_feed,
+                accounts,
+                now,
+            )?),
+        )),
+        OracleSource::Manual => {
+            let (key, px) = load_manual_price(cfg, vault, asset.mint, accounts, now)?;
+            Ok((key, Reading::spot_only(px)))
+        }
+        OracleSource::None => err!(ErrorCode::PriceAccountInvalid),
+    }
+}
+
+/// Prices sorted ascending by value at their finest common exponent, with that value.
+fn rank_prices(prices: impl Iterator<Item = Price> + Clone) -> Result<Vec<(u128, Price)>> {
+    let min_expo = prices.clone().map(|px| px.expo).min().unwrap_or(0);
+    let mut ranked: Vec<(u128, Price)> = prices
+        .map(|px| {
+            let scaled = (px.price.max(0) as u128)
+                .checked_mul(ten_pow_u128(px.expo - min_expo).ok_or(ErrorCode::MathOverflow)?)
+                .ok_or(ErrorCode::MathOverflow)?;
+            Ok((scaled, px))
+        })
+        .collect::<Result<_>>()?;
+    ranked.sort_by_key(|(scaled, _)| *scaled);
+    Ok(ranked)
+}
+
+/// Reading of one basket asset aggregated over the configured sources, in USD.
+///
+/// A stale source is skipped; every configured source must still be passed and readable,
+/// so leaving an account out cannot pick the quorum. The result is the lower median of the
+/// fresh spot prices; a majority of them, and
+/// at least `min_oracle_sources`, must sit within `max_oracle_deviation_bps` of it, so one
+/// outlier can neither move the price nor halt valuation. The EMA is the lower median of
+/// the same sources' EMAs. Every fresh spot reading is logged.
+fn load_usd_reading(
+    cfg: &PriceConfig,
+    vault: Pubkey,
+    asset: &CollateralAsset,
+    accounts: &[AccountInfo],
+    now: i64,
+) -> Result<Reading> {
+    let mut fresh: Vec<(OracleSource, Pubkey, Reading)> = Vec::with_capacity(MAX_ORACLE_SOURCES);
+    let mut last_err = error!(ErrorCode::PriceStale);
+    for source in cfg.oracle_sources.iter().filter(|s| **s != OracleSource::None) {
+        match load_source_price(cfg, *source, vault, asset, accounts, now) {
+            Ok((feed, r)) => {
+                require!(r.spot.price > 0 && r.ema.price > 0, ErrorCode::PriceNonPositive);
+                fresh.push((*source, feed, r))
+            }
+            Err(e) if e == error!(ErrorCode::PriceStale) => last_err = e,
+            Err(e) => return Err(e),
+        }
+    }
+    if fresh.is_empty() {
+        return Err(last_err);
+    }
+    require!(
+        fresh.len() >= cfg.min_oracle_sources as usize,
+        ErrorCode::OracleQuorumNotMet
+    );
+
+    let mid = (fresh.len() - 1) / 2;
+    let ranked = rank_prices(fresh.iter().map(|(_, _, r)| r.spot))?;
+    let (median, median_px) = ranked[mid];
+    let band = median
+        .checked_mul(cfg.max_oracle_deviation_bps as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let mut agreeing = 0usize;
+    for (scaled, _) in ranked.iter() {
+        let diff = scaled.abs_diff(median);
+        if diff.checked_mul(10_000).ok_or(ErrorCode::MathOverflow)? <= band {
+            agreeing += 1;
+        }
+    }
+    require!(
+        agreeing * 2 > ranked.len() && agreeing >= cfg.min_oracle_sources as usize,
+        ErrorCode::OracleDeviation
+    );
+
+    for (source, feed, r) in fresh.iter() {
+        emit!(PriceUsed {
+            source: *source,
+            feed: *feed,
+            price: r.spot.price,
+            conf: r.spot.conf,
+            expo: r.spot.expo,
+            publish_time: r.spot.publish_time,
+        });
+    }
+    let ema = rank_prices(fresh.iter().map(|(_, _, r)| r.ema))?[mid].1;
+    Ok(Reading {
+        spot: median_px,
+        ema,
+    })
+}
+
+/// Reading of one basket asset in the quote currency: the USD reading, divided by the
+/// quote/USD cross rate when `PriceConfig::quote` has one.
+fn load_asset_price(
+    state: &VaultState,
+    vault: Pubkey,
+    asset: &CollateralAsset,
+    accounts: &[AccountInfo],
+    now: i64,
+) -> Result<Reading> {
+    let cfg = &state.price_config;
+    let usd = load_usd_reading(cfg, vault, asset, accounts, now)?;
+    if !cfg.quote.cross_rate {
+        return Ok(usd);
+    }
+    let cross = load_usd_reading(cfg, vault, &quote_asset(state), accounts, now)?;
+    Ok(Reading {
+        spot: cross_price(&usd.spot, &cross.spot)?,
+        ema: cross_price(&usd.ema, &cross.ema)?,
+    })
+}
+
+/// The quote currency's cross feed, shaped as a basket asset so it goes through the same
+/// source aggregation; its manual price is posted for the debt mint.
+fn quote_asset(state: &VaultState) -> CollateralAsset {
+    let q = &state.price_config.quote;
+    CollateralAsset {
+        mint: state.loan.debt_mint,
+        decimals: state.loan.debt_decimals,
+        token_program: state.loan.debt_token_program,
+        ui_scaled: false,
+        price_feed: q.price_feed,
+        feed_id: q.feed_id,
+        switchboard_feed: q.switchboard_feed,
+        haircut_bps: 0,
+        amount_locked: 0,
+        last_price: 0,
+        last_expo: 0,
+        last_price_ts: 0,
+    }
+}
+
+/// Collateral/USD ÷ quote/USD at the collateral's exponent. Both legs round against the
+/// vault: price - conf uses the collateral's low and the quote's high (price + conf), and
+/// conf is widened so `price - conf` equals that floored quotient exactly.
+fn cross_price(px: &Price, quote: &Price) -> Result<Price> {
+    require!(px.price > 0 && quote.price > 0, ErrorCode::PriceNonPositive);
+    // value * 10^px.expo / (q * 10^quote.expo), kept at px.expo
+    let (scale_num, scale_den) = if quote.expo <= 0 {
+        (ten_pow_u128(-quote.expo).ok_or(ErrorCode::MathOverflow)?, 1u128)
+    } else {
+        (1u128, ten_pow_u128(quote.expo).ok_or(ErrorCode::MathOverflow)?)
+    };
+    let div = |num: u128, q: u128| -> Result<u128> {
+        let den = q.checked_mul(scale_den).ok_or(ErrorCode::MathOverflow)?;
+        Ok(num.checked_mul(scale_num).ok_or(ErrorCode::MathOverflow)? / den)
+    };
+
+    let spot = div(px.price as u128, quote.price as u128)?;
+    let low = div(
+        conservative_price(px)?,
+        (quote.price as u128)
+            .checked_add(quote.conf as u128)
+            .ok_or(ErrorCode::MathOverflow)?,
+    )?;
+    Ok(Price {
+        price: spot.try_into().map_err(|_| error!(ErrorCode::MathOverflow))?,
+        conf: (spot - low)
+            .try_into()
+            .map_err(|_| error!(ErrorCode::MathOverflow))?,
+        expo: px.expo,
+        publish_time: px.publish_time.min(quote.publish_time),
+    })
+}
+
+/// Staleness window as the u64 Pyth expects.
+fn staleness_secs(cfg: &PriceConfig) -> Result<u64> {
+    require!(cfg.max_price_staleness_secs >= 0, ErrorCode::InvalidRiskParams);
+    cfg.max_price_staleness_secs
+        .try_into()
+        .map_err(|_| error!(ErrorCode::InvalidRiskParams))
+}
+
+/// Spot prices for every basket asset holding collateral (`None` for empty slots), per raw
+/// unit after the UI amount conversion (see `ui_price`).
+fn load_basket_prices<'info>(
+    state: &VaultState,
+    vault: Pubkey,
+    mints: &[AccountInfo<'info>],
+    accounts: &[AccountInfo<'info>],
+    now: i64,
+) -> Result<Vec<Option<Price>>> {
+    state
+        .basket
+        .iter()
+        .map(|a| {
+            if a.amount_locked == 0 {
+                return Ok(None);
+            }
+            let px = load_asset_price(state, vault, a, accounts, now)?.spot;
+            Ok(Some(ui_price(a, px, mints, accounts, now)?))
+        })
+        .collect()
+}
+
+/// Prices under `PriceConfig::valuation_mode` for every basket asset holding collateral,
+/// used wherever collateral leaves the vault (LTV guard, quote caps, free margin).
+fn load_valuation_prices<'info>(
+    state: &VaultState,
+    vault: Pubkey,
+    mints: &[AccountInfo<'info>],
+    accounts: &[AccountInfo<'info>],
+    now: i64,
+) -> Result<Vec<Option<Price>>> {
+    let cfg = &state.price_config;
+    state
+        .basket
+        .iter()
+        .map(|a| {
+            if a.amount_locked == 0 {
+                return Ok(None);
+            }
+            let px = match cfg.valuation_mode {
+                ValuationMode::Spot => load_asset_price(state, vault, a, accounts, now)?.spot,
+                ValuationMode::Ema => load_asset_price(state, vault, a, accounts, now)?.ema,
+                ValuationMode::MinSpotEma => {
+                    let r = load_asset_price(state, vault, a, accounts, now)?;
+                    lower_conservative(r.spot, r.ema)?
+                }
+                ValuationMode::Twap => {
+                    load_twap_price(cfg, state.quote_epoch, vault, a.mint, accounts, now)?
+                }
+            };
+            Ok(Some(ui_price(a, px, mints, accounts, now)?))
+        })
+        .collect()
+}
+
+/// Time-weighted average of the observations of (`vault`, `mint`) inside the TWAP window;
+/// each observation counts until the next one (the newest until `now`), conf is the widest
+/// in the window. Needs two observations in the window and a fresh newest one, all recorded
+/// in the current quote currency (`quote_epoch`).
+fn load_twap_price(
+    cfg: &PriceConfig,
+    quote_epoch: u64,
+    vault: Pubkey,
+    mint: Pubkey,
+    accounts: &[AccountInfo],
+    now: i64,
+) -> Result<Price> {
+    let buffer = accounts
+        .iter()
+        .filter(|a| *a.owner == crate::ID)
+        .filter_map(|a| {
+            PriceObservations::try_deserialize(&mut &a.try_borrow_data().ok()?[..]).ok()
+        })
+        .find(|o| o.vault == vault && o.mint == mint)
+        .ok_or(ErrorCode::TwapUnavailable)?;
+    require!(buffer.quote_epoch == quote_epoch, ErrorCode::TwapUnavailable);
+
+    let start = now.saturating_sub(cfg.twap_window_secs);
+    let window: Vec<&PriceObservation> = buffer.chronological().filter(|o| o.ts >= start).collect();
+    require!(window.len() >= 2, ErrorCode::TwapUnavailable);
+    let newest = window[window.len() - 1];
+    require!(
+        newest.ts.saturating_add(staleness_secs(cfg)? as i64) >= now,
+        ErrorCode::PriceStale
+    );
+
+    let mut weighted: i128 = 0;
+    let mut total_secs: i128 = 0;
+    let mut conf: u64 = 0;
+    for (i, o) in window.iter().enumerate() {
+        let until = window.get(i + 1).map_or(now, |n| n.ts);
+        let secs = (until - o.ts) as i128;
+        weighted = weighted
+            .checked_add((o.price as i128).checked_mul(secs).ok_or(ErrorCode::MathOverflow)?)
+            .ok_or(ErrorCode::MathOverflow)?;
+        total_secs += secs;
+        conf = conf.max(o.conf);
+    }
+    require!(total_secs > 0, ErrorCode::TwapUnavailable);
+
+    Ok(Price {
+        price: (weighted / total_secs) as i64,
+        conf,
+        expo: TWAP_EXPO,
+        publish_time: newest.ts,
+    })
+}
+
+/// `px` at exponent `expo`: price rounded down, conf rounded up.
+fn rescale_price(px: &Price, expo: i32) -> Result<(i64, u64)> {
+    let (price, conf) = if px.expo >= expo {
+        let m = ten_pow_u128(px.expo - expo).ok_or(ErrorCode::MathOverflow)? as i128;
+        (
+            (px.price as i128).checked_mul(m).ok_or(ErrorCode::MathOverflow)?,
+            (px.conf as i128).checked_mul(m).ok_or(ErrorCode::MathOverflow)?,
+        )
+    } else {
+        let d = ten_pow_u128(expo - px.expo).ok_or(ErrorCode::MathOverflow)? as i128;
+        ((px.price as i128).div_euclid(d), (px.conf as i128 + d - 1) / d)
+    };
+    Ok((
+        price.try_into().map_err(|_| error!(ErrorCode::MathOverflow))?,
+        conf.try_into().map_err(|_| error!(ErrorCode::MathOverflow))?,
+    ))
+}
+
+/// Non-negative `price * 10^expo` restated at exponent `to` (`to <= expo`).
+fn scale_to_expo(price: i64, expo: i32, to: i32) -> Result<u128> {
+    (price.max(0) as u128)
+        .checked_mul(ten_pow_u128(expo - to).ok_or(ErrorCode::MathOverflow)?)
+        .ok_or_else(|| error!(ErrorCode::MathOverflow))
+}
+
+/// Whichever of `a` and `b` has the lower price - conf.
+fn lower_conservative(a: Price, b: Price) -> Result<Price> {
+    let expo = a.expo.min(b.expo);
+    let value = |px: &Price| -> Result<u128> {
+        conservative_price(px)?
+            .checked_mul(ten_pow_u128(px.expo - expo).ok_or(ErrorCode::MathOverflow)?)
+            .ok_or_else(|| error!(ErrorCode::MathOverflow))
+    };
+    Ok(if value(&b)? < value(&a)? { b } else { a })
+}
+
+/// Haircut-adjusted (lending) value of the whole basket at price - conf, quote 1e6.
+fn basket_value_quote_1e6(state: &VaultState, prices: &[Option<Price>]) -> Result<u128> {
+    let mut total: u128 = 0;
+    for (asset, px) in state.basket.iter().zip(prices) {
+        let Some(px) = px else { continue };
+        let market = quote_value_1e6(
+            asset.amount_locked,
+            asset.decimals,
+            conservative_price(px)?,
+            px.expo,
+        )?;
+        total = total
+            .checked_add(apply_haircut(market, asset.haircut_bps)?)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+    Ok(total)
+}
+
+/// Reject a price whose confidence interval exceeds `max_conf_bps` of the price.
+fn check_confidence(px: &Price, max_conf_bps: u16) -> Result<()> {
+    require!(px.price > 0, ErrorCode::PriceNonPositive);
+    require!(
+        (px.conf as u128) * 10_000 <= (max_conf_bps as u128) * (px.price as u128),
+        ErrorCode::PriceConfidenceTooWide
+    );
+    Ok(())
+}
+
+/// Market value (price - conf, no haircut) of slot `idx` and of the whole basket, quote 1e6.
+fn slot_and_basket_market_1e6(
+    state: &VaultState,
+    prices: &[Option<Price>],
+    idx: usize,
+) -> Result<(u128, u128)> {
+    let mut total: u128 = 0;
+    let mut slot: u128 = 0;
+    for (i, (asset, px)) in state.basket.iter().zip(prices).enumerate() {
+        let Some(px) = px else { continue };
+        let v = quote_value_1e6(
+            asset.amount_locked,
+            asset.decimals,
+            conservative_price(px)?,
+            px.expo,
+        )?;
+        total = total.checked_add(v).ok_or(ErrorCode::MathOverflow)?;
+        if i == idx {
+            slot = v;
+        }
+    }
+    Ok((slot, total))
+}
+
+/// Whether slot `idx` is at most `max_bps` of the basket's market value.
+fn concentration_within(
+    state: &VaultState,
+    prices: &[Option<Price>],
+    idx: usize,
+    max_bps: u16,
+) -> Result<bool> {
+    let (slot, total) = slot_and_basket_market_1e6(state, prices, idx)?;
+    Ok(slot.checked_mul(10_000).ok_or(ErrorCode::MathOverflow)?
+        <= total.checked_mul(max_bps as u128).ok_or(ErrorCode::MathOverflow)?)
+}
+
+/// After booking `amount` into slot `idx`, that mint's share of the basket market value must
+/// be within `max_bps`, unless the deposit does not raise its share (e.g. topping up a
+/// single-asset basket) or the basket was empty.
+fn check_concentration(
+    state: &VaultState,
+    prices: &[Option<Price>],
+    idx: usize,
+    amount: u64,
+    max_bps: u16,
+) -> Result<()> {
+    if concentration_within(state, prices, idx, max_bps)? {
+        return Ok(());
+    }
+    let (mint_after, total_after) = slot_and_basket_market_1e6(state, prices, idx)?;
+
+    let asset = &state.basket[idx];
+    let px = prices[idx].ok_or(ErrorCode::PriceAccountInvalid)?;
+    let mint_before = quote_value_1e6(
+        asset.amount_locked - amount,
+        asset.decimals,
+        conservative_price(&px)?,
+        px.expo,
+    )?;
+    let total_before = total_after - (mint_after - mint_before);
+    if total_before == 0 {
+        return Ok(());
+    }
+
+    let no_rise = mint_after
+        .checked_mul(total_before)
+        .ok_or(ErrorCode::MathOverflow)?
+        <= mint_before
+            .checked_mul(total_after)
+            .ok_or(ErrorCode::MathOverflow)?;
+    require!(no_rise, ErrorCode::ConcentrationLimitExceeded);
+    Ok(())
+}
+
+/// Withdrawal guards over the prices about to value collateral leaving the vault: rejects
+/// any price whose conf exceeds `max_conf_bps`, and returns a trip for the first asset whose
+/// price moved more than `max_price_move_bps` from its last accepted price within
+/// `price_move_window_secs`. Otherwise the prices become the last accepted ones.
+fn check_withdrawal_prices(
+    state: &mut VaultState,
+    prices: &[Option<Price>],
+    now: i64,
+) -> Result<Option<CircuitBreakerTripped>> {
+    let cfg = state.price_config;
+    for px in prices.iter().flatten() {
+        check_confidence(px, cfg.max_conf_bps)?;
+    }
+
+    for (asset, px) in state.basket.iter().zip(prices) {
+        let Some(px) = px else { continue };
+        if cfg.price_move_window_secs == 0
+            || asset.last_price_ts == 0
+            || now - asset.last_price_ts > cfg.price_move_window_secs
+        {
+            continue;
+        }
+        let common = px.expo.min(asset.last_expo);
+        let last = scale_to_expo(asset.last_price, asset.last_expo, common)?;
+        let moved = scale_to_expo(px.price, px.expo, common)?.abs_diff(last);
+        let move_bps = moved
+            .checked_mul(10_000)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_div(last)
+            .unwrap_or(u128::MAX);
+        if move_bps > cfg.max_price_move_bps as u128 {
+            return Ok(Some(CircuitBreakerTripped {
+                mint: asset.mint,
+                last_price: asset.last_price,
+                last_expo: asset.last_expo,
+                last_price_ts: asset.last_price_ts,
+                price: px.price,
+                expo: px.expo,
+                publish_time: px.publish_time,
+                move_bps: move_bps.min(u64::MAX as u128) as u64,
+            }));
+        }
+    }
+
+    for (asset, px) in state.basket.iter_mut().zip(prices) {
+        if let Some(px) = px {
+            asset.last_price = px.price;
+            asset.last_expo = px.expo;
+            asset.last_price_ts = now;
+        }
+    }
+    Ok(None)
+}
+
+/// Freeze the vault, keeping the start of a freeze already in progress.
+fn freeze(state: &mut VaultState, now: i64) {
+    if !state.is_frozen {
+        state.is_frozen = true;
+        state.frozen_at = now;
+    }
+}
+
+/// Pause the vault on a price-move trip; the custodian investigates before unpausing.
+fn trip_circuit_breaker(state: &mut VaultState, trip: CircuitBreakerTripped, now: i64) {
+    freeze(state, now);
+    emit!(trip);
+    emit!(Paused {});
+    emit!(StateSignal {
+        paused: true,
+        open_release_proposals: state.open_release_proposals,
+        basket_assets: state.basket.len() as u8
+    });
+}
+
+/// Start a new daily cap window (token and quote counters) once 24h have passed.
+fn roll_cap_window(state: &mut VaultState, now: i64) {
+    if now - state.last_cap_reset_ts >= 86_400 {
+        state.last_cap_reset_ts = now;
+        state.released_today = 0;
+        state.released_today_quote_1e6 = 0;
+    }
+}
+
+/// Enforce the per-tx and daily quote caps on `quote_1e6` leaving the vault and record it.
+fn charge_quote_caps(state: &mut VaultState, quote_1e6: u128) -> Result<()> {
+    require!(
+        quote_1e6 <= state.price_config.max_single_release_quote_1e6 as u128,
+        ErrorCode::QuoteCapExceeded
+    );
+    let new_today_quote = (state.released_today_quote_1e6 as u128)
+        .checked_add(quote_1e6)
+        .ok_or(ErrorCode::MathOverflow)?;
+    require!(
+        new_today_quote <= state.price_config.daily_cap_quote_1e6 as u128,
+        ErrorCode::QuoteCapExceeded
+    );
+    state.released_today_quote_1e6 = new_today_quote as u64;
+    Ok(())
+}
+
+/// Conservative price: price - conf (must stay positive).
+fn conservative_price(px: &Price) -> Result<u128> {
+    let p_conservative = (px.price as i128)
+        .checked_sub(px.conf as i128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    if p_conservative <= 0 {
+        return err!(ErrorCode::PriceNonPositive);
+    }
+    Ok(p_conservative as u128)
+}
+
+/// Convert a token amount to quote 1e6 using integer math (floor):
+/// quote_1e6 = amount * price * 10^6 / (10^mint_decimals * 10^(-expo))
+fn quote_value_1e6(amount: u64, mint_decimals: u8, price: u128, expo: i32) -> Result<u128> {
+    let denom_exp: i32 = mint_decimals as i32 - expo; // = mint_dec + (-expo)
+    let denom = ten_pow_u128(denom_exp).ok_or(ErrorCode::MathOverflow)?;
+    let num = (amount as u128)
+        .checked_mul(price)
+        .and_then(|v| v.checked_mul(1_000_000u128))
+        .ok_or(ErrorCode::MathOverflow)?;
+    Ok(num.checked_div(denom).ok_or(ErrorCode::MathOverflow)?)
+}
+
+/// Debt-mint amount -> quote 1e6; the debt mint is the quote currency at par.
+/// Rounds up so debt is never understated.
+fn debt_quote_1e6(amount: u64, debt_decimals: u8) -> Result<u128> {
+    let denom = ten_pow_u128(debt_decimals as i32).ok_or(ErrorCode::MathOverflow)?;
+    let num = (amount as u128)
+        .checked_mul(1_000_000u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    Ok(num.div_ceil(denom))
+}
+
+/// `v * bps / 10_000` (floor).
+#[inline]
+fn apply_bps(v: u128, bps: u128) -> Result<u128> {
+    Ok(v.checked_mul(bps).ok_or(ErrorCode::MathOverflow)? / 10_000)
+}
+
+/// Lending value after a haircut: `v * (10_000 - haircut_bps) / 10_000` (floor).
+#[inline]
+fn apply_haircut(v: u128, haircut_bps: u16) -> Result<u128> {
+    apply_bps(v, 10_000u128.saturating_sub(haircut_bps as u128))
+}
+
+/// Quote price -> quote 1e6 per whole token (floor).
+fn price_per_token_1e6(price: u128, expo: i32) -> Result<u128> {
+    // price * 10^expo * 10^6
+    let shift = expo + 6;
+    if shift >= 0 {
+        price
+            .checked_mul(ten_pow_u128(shift).ok_or(ErrorCode::MathOverflow)?)
+            .ok_or_else(|| error!(ErrorCode::MathOverflow))
+    } else {
+        Ok(price / ten_pow_u128(-shift).ok_or(ErrorCode::MathOverflow)?)
+    }
+}
+
+/// Debt-mint cost of `collateral` at `price_1e6` (quote 1e6 per whole token), rounded up
+/// against the bidder. The debt mint is the quote currency at par.
+fn auction_cost(
+    collateral: u64,
+    mint_decimals: u8,
+    debt_decimals: u8,
+    price_1e6: u64,
+) -> Result<u64> {
+    let num = (collateral as u128)
+        .checked_mul(price_1e6 as u128)
+        .and_then(|v| v.checked_mul(ten_pow_u128(debt_decimals as i32)?))
+        .ok_or(ErrorCode::MathOverflow)?;
+    let den = ten_pow_u128(mint_decimals as i32)
+        .and_then(|v| v.checked_mul(1_000_000))
+        .ok_or(ErrorCode::MathOverflow)?;
+    to_u64(num.div_ceil(den))
+}
+
+/// Collateral that `debt_amount` buys at `price_1e6` (floor); inverse of `auction_cost`.
+fn auction_tokens_for(
+    debt_amount: u64,
+    mint_decimals: u8,
+    debt_decimals: u8,
+    price_1e6: u64,
+) -> Result<u64> {
+    let num = (debt_amount as u128)
+        .checked_mul(ten_pow_u128(mint_decimals as i32).ok_or(ErrorCode::MathOverflow)?)
+        .and_then(|v| v.checked_mul(1_000_000))
+        .ok_or(ErrorCode::MathOverflow)?;
+    let den = (price_1e6 as u128)
+        .checked_mul(ten_pow_u128(debt_decimals as i32).ok_or(ErrorCode::MathOverflow)?)
+        .ok_or(ErrorCode::MathOverflow)?;
+    require!(den > 0, ErrorCode::PriceNonPositive);
+    to_u64((num / den).min(u64::MAX as u128))
+}
+
+/// Collateral / debt in bps; no debt counts as infinitely healthy.
+fn health_bps(collateral_quote_1e6: u128, debt_quote_1e6: u128) -> Result<u128> {
+    if debt_quote_1e6 == 0 {
+        return Ok(u128::MAX);
+    }
+    Ok(collateral_quote_1e6
+        .checked_mul(10_000)
+        .ok_or(ErrorCode::MathOverflow)?
+        / debt_quote_1e6)
+}
+
+#[inline]
+fn to_u64(v: u128) -> Result<u64> {
+    v.try_into().map_err(|_| error!(ErrorCode::MathOverflow))
+}
+
+/// 10^exp as u128 (exp must be >= 0 and small enough to fit)
+#[inline]
+fn ten_pow_u128(exp: i32) -> Option<u128> {
+    if exp < 0 {
+        return None;
+    }
+    let mut v: u128 = 1;
+    for _ in 0..(exp as u32) {
+        v = v.checked_mul(10)?;
+    }
+    Some(v)
+}
+
+
+/* ---------------------------------- Tests --------------------------------- */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    /// Backing storage for an `AccountInfo` built in a test.
+    struct TestAccount {
+        key: Pubkey,
+        owner: Pubkey,
+        lamports: u64,
+        data: Vec<u8>,
+    }
+
+    impl TestAccount {
+        fn new(owner: Pubkey, data: Vec<u8>) -> Self {
+            Self {
+                key: Pubkey::new_unique(),
+                owner,
+                lamports: 1,
+                data,
+            }
+        }
+
+        fn info(&mut self) -> AccountInfo<'_> {
+            AccountInfo::new(
+                &self.key,
+                false,
+                false,
+                &mut self.lamports,
+                &mut self.data,
+                &self.owner,
+                false,
+                0,
+            )
+        }
+    }
+
+    fn err_of<T>(r: Result<T>) -> Error {
+        match r {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e,
+        }
+    }
+
+    /// The defaults `initialize_vault` writes, with price checks on.
+    fn config() -> PriceConfig {
+        PriceConfig {
+            enabled: true,
+            max_ltv_bps: 7000,
+            max_single_release_quote_1e6: 1_000_000_000,
+            daily_cap_quote_1e6: 5_000_000_000,
+            max_price_staleness_secs: 90,
+            maintenance_ratio_bps: 12_000,
+            margin_cure_secs: 86_400,
+            auction_premium_bps: 500,
+            auction_floor_bps: 8_000,
+            auction_duration_secs: 3_600,
+            liquidation_fee_bps: 200,
+            oracle_sources: [OracleSource::PythPull, OracleSource::None, OracleSource::None],
+            min_oracle_sources: 1,
+            max_oracle_deviation_bps: 200,
+            valuation_mode: ValuationMode::Spot,
+            twap_window_secs: 1_800,
+            twap_min_interval_secs: 60,
+            max_conf_bps: 200,
+            max_price_move_bps: 1_000,
+            price_move_window_secs: 3_600,
+            quote: QuoteConfig {
+                symbol: *b"USD\0\0\0\0\0",
+                cross_rate: false,
+                price_feed: Pubkey::default(),
+                feed_id: [0; 32],
+                switchboard_feed: Pubkey::default(),
+            },
+            manual_max_staleness_secs: 86_400,
+            manual_max_change_bps: 1_000,
+            manual_change_window_secs: 86_400,
+            min_verification: VerificationLevel::Full,
+        }
+    }
+
+    /// A 6-decimal SPL Token basket asset with fresh feeds and no reference price.
+    fn asset(amount_locked: u64) -> CollateralAsset {
+        CollateralAsset {
+            mint: Pubkey::new_unique(),
+            decimals: 6,
+            token_program: anchor_spl::token::ID,
+            ui_scaled: false,
+            price_feed: Pubkey::default(),
+            feed_id: [7; 32],
+            switchboard_feed: Pubkey::new_unique(),
+            haircut_bps: 0,
+            amount_locked,
+            last_price: 0,
+            last_expo: 0,
+            last_price_ts: 0,
+        }
+    }
+
+    fn px(price: i64, conf: u64, expo: i32) -> Price {
+        Price {
+            price,
+            conf,
+            expo,
+            publish_time: NOW,
+        }
+    }
+
+    /// An active 2-of-3 vault over `basket` with no loan drawn.
+    fn vault(basket: Vec<CollateralAsset>) -> VaultState {
+        VaultState {
+            basket,
+            vault_authority_bump: 255,
+            custodian: Pubkey::new_unique(),
+            borrower: Pubkey::new_unique(),
+            lender: Pubkey::new_unique(),
+            is_frozen: false,
+            frozen_at: 0,
+            release_nonce: 0,
+            open_release_proposals: 0,
+            open_aux_accounts: 0,
+            executor: Pubkey::default(),
+            governance_nonce: 0,
+            roles_epoch: 0,
+            quote_epoch: 0,
+            rent_payer: Pubkey::new_unique(),
+            accepted_mint_risks: 0,
+            threshold: 2,
+            quorum: QuorumPolicy::default(),
+            risk_timelock_secs: DEFAULT_RISK_TIMELOCK_SECS,
+            last_cap_reset_ts: NOW,
+            released_today: 0,
+            released_today_quote_1e6: 0,
+            price_config: config(),
+            loan: LoanTerms {
+                debt_mint: Pubkey::new_unique(),
+                debt_decimals: 6,
+                debt_token_program: anchor_spl::token::ID,
+                principal: 0,
+                apr_bps: 0,
+                start_ts: 0,
+                maturity_ts: NOW + SECS_PER_YEAR as i64,
+                accrued_interest: 0,
+                last_accrual_ts: 0,
+                accrual_carry: 0,
+            },
+            status: VaultStatus::Active,
+            margin_call_deadline: 0,
+        }
+    }
+
+    /* ------------------------------ Pyth pull ------------------------------ */
+
+    fn price_update(feed_id: [u8; 32], level: VerificationLevel, publish_time: i64) -> Vec<u8> {
+        let update = PriceUpdateV2 {
+            write_authority: Pubkey::new_unique(),
+            verification_level: level,
+            price_message: PriceFeedMessage {
+                feed_id,
+                price: 10_000_000_000,
+                conf: 5_000_000,
+                exponent: -8,
+                publish_time,
+                prev_publish_time: publish_time - 1,
+                ema_price: 9_900_000_000,
+                ema_conf: 6_000_000,
+            },
+            posted_slot: 1,
+        };
+        let mut data = PRICE_UPDATE_V2_DISCRIMINATOR.to_vec();
+        update.serialize(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn pull_update_decodes() {
+        let mut acc = TestAccount::new(
+            PYTH_RECEIVER_PROGRAM_ID,
+            price_update([7; 32], VerificationLevel::Full, NOW - 10),
+        );
+        let r = load_pyth_pull_price(&config(), &[7; 32], &[acc.info()], NOW).unwrap();
+        assert_eq!(r.spot.price, 10_000_000_000);
+        assert_eq!(r.spot.conf, 5_000_000);
+        assert_eq!(r.spot.expo, -8);
+        assert_eq!(r.ema.price, 9_900_000_000);
+        assert_eq!(r.ema.conf, 6_000_000);
+    }
+
+    #[test]
+    fn pull_update_checks_owner_and_discriminator() {
+        let data = price_update([7; 32], VerificationLevel::Full, NOW);
+        let mut foreign = TestAccount::new(Pubkey::new_unique(), data.clone());
+        assert_eq!(
+            err_of(PriceUpdateV2::try_from_account(&foreign.info())),
+            error!(ErrorCode::PriceAccountInvalid)
+        );
+
+        let mut bad_disc = data;
+        bad_disc[0] ^= 1;
+        let mut acc = TestAccount::new(PYTH_RECEIVER_PROGRAM_ID, bad_disc);
+        assert_eq!(
+            err_of(PriceUpdateV2::try_from_account(&acc.info())),
+            error!(ErrorCode::PriceAccountInvalid)
+        );
+        // Neither qualifies as an update for the feed
+        assert_eq!(
+            err_of(load_pyth_pull_price(&config(), &[7; 32], &[foreign.info(), acc.info()], NOW)),
+            error!(ErrorCode::PriceAccountInvalid)
+        );
+    }
+
+    #[test]
+    fn pull_update_needs_min_verification() {
+        let partial = VerificationLevel::Partial { num_signatures: 5 };
+        let mut acc =
+            TestAccount::new(PYTH_RECEIVER_PROGRAM_ID, price_update([7; 32], partial, NOW));
+        assert_eq!(
+            err_of(load_pyth_pull_price(&config(), &[7; 32], &[acc.info()], NOW)),
+            error!(ErrorCode::InsufficientVerification)
+        );
+
+        let mut cfg = config();
+        cfg.min_verification = VerificationLevel::Partial { num_signatures: 5 };
+        assert!(load_pyth_pull_price(&cfg, &[7; 32], &[acc.info()], NOW).is_ok());
+        cfg.min_verification = VerificationLevel::Partial { num_signatures: 6 };
+        assert_eq!(
+            err_of(load_pyth_pull_price(&cfg, &[7; 32], &[acc.info()], NOW)),
+            error!(ErrorCode::InsufficientVerification)
+        );
+    }
+
+    #[test]
+    fn pull_update_staleness() {
+        let cfg = config();
+        let edge = NOW - cfg.max_price_staleness_secs;
+        let mut fresh = TestAccount::new(
+            PYTH_RECEIVER_PROGRAM_ID,
+            price_update([7; 32], VerificationLevel::Full, edge),
+        );
+        assert!(load_pyth_pull_price(&cfg, &[7; 32], &[fresh.info()], NOW).is_ok());
+
+        let mut stale = TestAccount::new(
+            PYTH_RECEIVER_PROGRAM_ID,
+            price_update([7; 32], VerificationLevel::Full, edge - 1),
+        );
+        assert_eq!(
+            err_of(load_pyth_pull_price(&cfg, &[7; 32], &[stale.info()], NOW)),
+            error!(ErrorCode::PriceStale)
+        );
+    }
+
+    #[test]
+    fn pull_update_feed_id_mismatch() {
+        let mut acc = TestAccount::new(
+            PYTH_RECEIVER_PROGRAM_ID,
+            price_update([8; 32], VerificationLevel::Full, NOW),
+        );
+        assert_eq!(
+            err_of(load_pyth_pull_price(&config(), &[7; 32], &[acc.info()], NOW)),
+            error!(ErrorCode::PriceAccountInvalid)
+        );
+    }
+
+    /* ------------------------------- Quorum -------------------------------- */
+
+    const CUSTODIAN: u8 = 1 << 0;
+    const BORROWER: u8 = 1 << 1;
+    const LENDER: u8 = 1 << 2;
+
+    #[test]
+    fn quorum_rule_inherits_threshold() {
+        let state = vault(vec![]);
+        let inherit = QuorumRule::default();
+        assert_eq!(
+            err_of(check_rule(&state, inherit, CUSTODIAN)),
+            error!(ErrorCode::NotEnoughApprovals)
+        );
+        assert!(check_rule(&state, inherit, CUSTODIAN | BORROWER).is_ok());
+    }
+
+    #[test]
+    fn quorum_rule_needs_required_roles() {
+        let state = vault(vec![]);
+        let lender_alone = QuorumRule {
+            min_approvals: 1,
+            required_mask: LENDER,
+        };
+        assert!(check_rule(&state, lender_alone, LENDER).is_ok());
+        // Enough approvals, but not the required one
+        assert_eq!(
+            err_of(check_rule(&state, lender_alone, CUSTODIAN | BORROWER)),
+            error!(ErrorCode::RequiredApproverMissing)
+        );
+    }
+
+    #[test]
+    fn quorum_uses_the_action_entry() {
+        let mut state = vault(vec![]);
+        state.quorum.close = QuorumRule {
+            min_approvals: 3,
+            required_mask: 0,
+        };
+        let two = CUSTODIAN | LENDER;
+        assert!(require_quorum(&state, QuorumAction::Release, two).is_ok());
+        assert_eq!(
+            err_of(require_quorum(&state, QuorumAction::Close, two)),
+            error!(ErrorCode::NotEnoughApprovals)
+        );
+        assert!(require_quorum(&state, QuorumAction::Close, ALL_ROLES_MASK).is_ok());
+    }
+
+    /* ------------------------------ Close vault ----------------------------- */
+
+    /// Program account laid out like the runtime's serialized input, which `realloc` writes
+    /// into: the original data length sits right before the key, the data length right
+    /// before the data.
+    #[repr(C)]
+    struct SerializedKey {
+        original_data_len: u32,
+        key: Pubkey,
+    }
+
+    struct PdaAccount {
+        key: SerializedKey,
+        owner: Pubkey,
+        lamports: u64,
+        buf: Vec<u8>,
+    }
+
+    impl PdaAccount {
+        fn new<T: AccountSerialize>(account: &T) -> Self {
+            let mut data = Vec::new();
+            account.try_serialize(&mut data).unwrap();
+            let mut buf = (data.len() as u64).to_le_bytes().to_vec();
+            buf.extend_from_slice(&data);
+            Self {
+                key: SerializedKey {
+                    original_data_len: data.len() as u32,
+                    key: Pubkey::new_unique(),
+                },
+                owner: crate::ID,
+                lamports: 2_000_000,
+                buf,
+            }
+        }
+
+        fn info(&mut self) -> AccountInfo<'_> {
+            AccountInfo::new(
+                &self.key.key,
+                false,
+                true,
+                &mut self.lamports,
+                &mut self.buf[8..],
+                &self.owner,
+                false,
+                0,
+            )
+        }
+    }
+
+    fn manual_price_for(vault: Pubkey) -> ManualPrice {
+        ManualPrice {
+            vault,
+            mint: Pubkey::new_unique(),
+            price: 100,
+            expo: -2,
+            publish_time: NOW,
+            lender_cosigned: false,
+            ref_price: 100,
+            ref_expo: -2,
+            ref_ts: NOW,
+            bump: 255,
+        }
+    }
+
+    #[test]
+    fn close_aux_accounts_refunds_the_vault_pdas() {
+        let vault_key = Pubkey::new_unique();
+        let mut schedule = PdaAccount::new(&EligibilitySchedule {
+            vault: vault_key,
+            entries: vec![],
+            updated_at: NOW,
+            bump: 255,
+        });
+        let mut manual = PdaAccount::new(&manual_price_for(vault_key));
+        let mut obs = PdaAccount::new(&PriceObservations {
+            vault: vault_key,
+            mint: Pubkey::new_unique(),
+            quote_epoch: 0,
+            head: 0,
+            len: 0,
+            observations: [PriceObservation::default(); TWAP_CAPACITY],
+            bump: 255,
+        });
+        // A hook program's account and a non-aux account of ours are left alone
+        let mut hook = TestAccount::new(Pubkey::new_unique(), vec![1; 64]);
+        let mut state = PdaAccount::new(&vault(vec![]));
+        let mut payer = TestAccount::new(System::id(), vec![]);
+
+        let infos = [
+            schedule.info(),
+            hook.info(),
+            manual.info(),
+            state.info(),
+            obs.info(),
+        ];
+        let payer_info = payer.info();
+        assert_eq!(close_aux_accounts(vault_key, infos.iter(), &payer_info).unwrap(), 3);
+        assert_eq!(payer_info.lamports(), 1 + 3 * 2_000_000);
+        for closed in [&infos[0], &infos[2], &infos[4]] {
+            assert_eq!(closed.lamports(), 0);
+            assert_eq!(closed.data_len(), 0);
+        }
+        assert_eq!(infos[1].lamports(), 1);
+        assert_eq!(infos[3].lamports(), 2_000_000);
+    }
+
+    #[test]
+    fn close_aux_accounts_rejects_another_vaults_pda() {
+        let mut manual = PdaAccount::new(&manual_price_for(Pubkey::new_unique()));
+        let mut payer = TestAccount::new(System::id(), vec![]);
+        let infos = [manual.info()];
+        let payer_info = payer.info();
+        assert_eq!(
+            err_of(close_aux_accounts(Pubkey::new_unique(), infos.iter(), &payer_info)),
+            error!(ErrorCode::Unauthorized)
+        );
+        assert_eq!(infos[0].lamports(), 2_000_000);
+    }
+
+    /* -------------------------------- Loan --------------------------------- */
+
+    fn loan(principal: u64, apr_bps: u16) -> LoanTerms {
+        LoanTerms {
+            principal,
+            apr_bps,
+            ..vault(vec![]).loan
+        }
+    }
+
+    #[test]
+    fn accrue_simple_interest() {
+        // 1,000 units at 10% for a year
+        let mut l = loan(1_000_000_000, 1_000);
+        l.accrue(SECS_PER_YEAR as i64).unwrap();
+        assert_eq!(l.accrued_interest, 100_000_000);
+        assert_eq!(l.outstanding().unwrap(), 1_100_000_000);
+
+        // Time going backwards accrues nothing
+        l.accrue(10).unwrap();
+        assert_eq!(l.accrued_interest, 100_000_000);
+    }
+
+    #[test]
+    fn accrue_in_steps_matches_one_shot() {
+        let mut once = loan(1_234_567, 733);
+        let mut steps = once;
+        once.accrue(86_400 * 30).unwrap();
+        for t in (1..=30).map(|d| d * 86_400) {
+            steps.accrue(t).unwrap();
+        }
+        assert_eq!(steps.accrued_interest, once.accrued_interest);
+        assert_eq!(steps.accrual_carry, once.accrual_carry);
+    }
+
+    /* ------------------------------- Auction ------------------------------- */
+
+    #[test]
+    fn auction_price_decays_linearly_to_floor() {
+        let a = Auction {
+            vault: Pubkey::new_unique(),
+            starter: Pubkey::new_unique(),
+            mint: Pubkey::new_unique(),
+            start_ts: NOW,
+            end_ts: NOW + 3_600,
+            start_price_1e6: 2_100_000,
+            floor_price_1e6: 1_600_000,
+            collateral_for_sale: 1_000_000_000,
+            collateral_sold: 0,
+            debt_target: 1_000_000_000,
+            fee_target: 20_000_000,
+            proceeds: 0,
+            bump: 255,
+        };
+        assert_eq!(a.current_price_1e6(NOW).unwrap(), 2_100_000);
+        assert_eq!(a.current_price_1e6(NOW + 1_800).unwrap(), 1_850_000);
+        assert_eq!(a.current_price_1e6(NOW + 3_600).unwrap(), 1_600_000);
+        assert_eq!(a.current_price_1e6(NOW + 99_999).unwrap(), 1_600_000);
+    }
+
+    #[test]
+    fn bid_sizing_rounds_against_the_bidder() {
+        // 9-decimal collateral at 2.0, 6-decimal debt mint
+        let tokens = auction_tokens_for(1_000_001, 9, 6, 2_000_000).unwrap();
+        assert_eq!(tokens, 500_000_500);
+        assert_eq!(auction_cost(tokens, 9, 6, 2_000_000).unwrap(), 1_000_001);
+        // Never more collateral than the debt pays for; dust still costs a unit
+        assert!(auction_cost(tokens + 1, 9, 6, 2_000_000).unwrap() > 1_000_001);
+        assert_eq!(auction_cost(1, 9, 6, 2_000_000).unwrap(), 1);
+        assert_eq!(
+            err_of(auction_tokens_for(1, 9, 6, 0)),
+            error!(ErrorCode::PriceNonPositive)
+        );
+    }
+
+    /* ---------------------------- Concentration ---------------------------- */
+
+    #[test]
+    fn concentration_limit() {
+        // 700 + 300 at 1.00 each, 60% cap
+        let state = vault(vec![asset(700_000_000), asset(300_000_000)]);
+        let prices = vec![Some(px(100_000_000, 0, -8)); 2];
+        // Adding 100 to the 70% slot raises its share above the cap
+        assert_eq!(
+            err_of(check_concentration(&state, &prices, 0, 100_000_000, 6_000)),
+            error!(ErrorCode::ConcentrationLimitExceeded)
+        );
+        // Adding to the 30% slot is within the cap
+        assert!(check_concentration(&state, &prices, 1, 100_000_000, 6_000).is_ok());
+    }
+
+    #[test]
+    fn concentration_allows_topping_up_a_single_asset() {
+        let state = vault(vec![asset(500_000_000), asset(0)]);
+        let prices = vec![Some(px(100_000_000, 0, -8)), None];
+        assert!(check_concentration(&state, &prices, 0, 100_000_000, 5_000).is_ok());
+        // First deposit into an empty basket
+        assert!(check_concentration(&state, &prices, 0, 500_000_000, 5_000).is_ok());
+    }
+
+    /* ----------------------------- Switchboard ----------------------------- */
+
+    // `PullFeedAccountData` and its parts as declared in switchboard-on-demand 0.3.8
+    // (src/on_demand/accounts/pull_feed.rs). The program reads fixed offsets; these
+    // transcriptions pin them and build test accounts field by field.
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct SbOracleSubmission {
+        oracle: Pubkey,
+        slot: u64,
+        landed_at: u64,
+        value: i128,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct SbCurrentResult {
+        value: i128,
+        std_dev: i128,
+        mean: i128,
+        range: i128,
+        min_value: i128,
+        max_value: i128,
+        num_samples: u8,
+        submission_idx: u8,
+        padding1: [u8; 6],
+        slot: u64,
+        min_slot: u64,
+        max_slot: u64,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct SbCompactResult {
+        std_dev: f32,
+        mean: f32,
+        slot: u64,
+    }
+
+    #[repr(C)]
+    struct SbPullFeed {
+        submissions: [SbOracleSubmission; 32],
+        authority: Pubkey,
+        queue: Pubkey,
+        feed_hash: [u8; 32],
+        initialized_at: i64,
+        permissions: u64,
+        max_variance: u64,
+        min_responses: u32,
+        name: [u8; 32],
+        padding1: [u8; 2],
+        historical_result_idx: u8,
+        min_sample_size: u8,
+        last_update_timestamp: i64,
+        lut_slot: u64,
+        reserved1: [u8; 32],
+        result: SbCurrentResult,
+        max_staleness: u32,
+        padding2: [u8; 12],
+        historical_results: [SbCompactResult; 32],
+        ebuf4: [u8; 8],
+        ebuf3: [u8; 24],
+        submission_timestamps: [i64; 32],
+    }
+
+    impl SbPullFeed {
+        fn zeroed() -> Box<Self> {
+            // SAFETY: plain integers, floats and byte arrays; all-zero is a valid value
+            Box::new(unsafe { std::mem::zeroed() })
+        }
+
+        /// Discriminator followed by the struct's bytes, as the account stores it.
+        fn account_data(&self) -> Vec<u8> {
+            // SAFETY: repr(C) without padding (checked by `switchboard_layout_matches`)
+            let body = unsafe {
+                std::slice::from_raw_parts(
+                    self as *const Self as *const u8,
+                    std::mem::size_of::<Self>(),
+                )
+            };
+            [&SB_PULL_FEED_DISCRIMINATOR[..], body].concat()
+        }
+    }
+
+    #[test]
+    fn switchboard_layout_matches() {
+        use std::mem::{offset_of, size_of};
+        let result = offset_of!(SbPullFeed, result);
+        assert_eq!(size_of::<SbPullFeed>(), SB_PULL_FEED_LEN);
+        assert_eq!(result + offset_of!(SbCurrentResult, value), SB_RESULT_VALUE_OFFSET);
+        assert_eq!(result + offset_of!(SbCurrentResult, std_dev), SB_RESULT_STD_DEV_OFFSET);
+        assert_eq!(
+            result + offset_of!(SbCurrentResult, submission_idx),
+            SB_RESULT_SUBMISSION_IDX_OFFSET
+        );
+        assert_eq!(result + offset_of!(SbCurrentResult, slot), SB_RESULT_SLOT_OFFSET);
+        assert_eq!(offset_of!(SbPullFeed, submission_timestamps), SB_SUBMISSION_TS_OFFSET);
+        // No implicit padding anywhere: the fields account for every byte
+        assert_eq!(size_of::<SbCurrentResult>(), 6 * 16 + 8 + 3 * 8);
+        assert_eq!(
+            size_of::<SbPullFeed>(),
+            32 * 64 + 3 * 32 + 3 * 8 + 4 + 32 + 4 + 2 * 8 + 32 + 128 + 4 + 12 + 32 * 16 + 32
+                + 32 * 8
+        );
+        // Anchor account discriminator: sha256("account:PullFeedAccountData")[..8]
+        let hash = anchor_lang::solana_program::hash::hash(b"account:PullFeedAccountData");
+        assert_eq!(hash.to_bytes()[..8], SB_PULL_FEED_DISCRIMINATOR);
+    }
+
+    #[test]
+    fn switchboard_feed_decodes() {
+        let mut feed = SbPullFeed::zeroed();
+        // 123.456789012345678901 ± 0.000000001 from submission 3
+        feed.result.value = 123_456_789_012_345_678_901;
+        feed.result.std_dev = 1_000_000_000;
+        feed.result.submission_idx = 3;
+        feed.result.slot = 250_000_000;
+        feed.submission_timestamps[3] = NOW - 5;
+        let mut acc = TestAccount::new(SWITCHBOARD_ON_DEMAND_PROGRAM_ID, feed.account_data());
+
+        let px = load_switchboard_price(&config(), acc.key, &[acc.info()], NOW).unwrap();
+        assert_eq!(px.price, 12_345_678_901); // floored to 1e-8
+        assert_eq!(px.conf, 1); // rounded up
+        assert_eq!(px.expo, SB_EXPO);
+        assert_eq!(px.publish_time, NOW - 5);
+
+        // No result yet
+        feed.result.slot = 0;
+        let mut empty = TestAccount::new(SWITCHBOARD_ON_DEMAND_PROGRAM_ID, feed.account_data());
+        assert_eq!(
+            err_of(load_switchboard_price(&config(), empty.key, &[empty.info()], NOW)),
+            error!(ErrorCode::PriceStale)
+        );
+    }
+
+    #[test]
+    fn switchboard_checks_owner_and_discriminator() {
+        let data = switchboard_feed(10_000_000_000, NOW);
+        let mut foreign = TestAccount::new(Pubkey::new_unique(), data.clone());
+        assert_eq!(
+            err_of(load_switchboard_price(&config(), foreign.key, &[foreign.info()], NOW)),
+            error!(ErrorCode::PriceAccountInvalid)
+        );
+        let mut bad_disc = data.clone();
+        bad_disc[0] ^= 1;
+        let mut acc = TestAccount::new(SWITCHBOARD_ON_DEMAND_PROGRAM_ID, bad_disc);
+        assert_eq!(
+            err_of(load_switchboard_price(&config(), acc.key, &[acc.info()], NOW)),
+            error!(ErrorCode::PriceAccountInvalid)
+        );
+        let mut short = TestAccount::new(SWITCHBOARD_ON_DEMAND_PROGRAM_ID, data[..3000].to_vec());
+        assert_eq!(
+            err_of(load_switchboard_price(&config(), short.key, &[short.info()], NOW)),
+            error!(ErrorCode::PriceAccountInvalid)
+        );
+    }
+
+    /* ------------------------- Source aggregation -------------------------- */
+
+    fn switchboard_feed(price_1e8: i64, publish_time: i64) -> Vec<u8> {
+        let mut feed = SbPullFeed::zeroed();
+        feed.result.value = price_1e8 as i128 * SB_RESCALE;
+        feed.result.slot = 1;
+        feed.submission_timestamps[0] = publish_time;
+        feed.account_data()
+    }
+
+    /// Pyth pull at 100.00, Switchboard at `sb_1e8` and a manual price of `manual_cents`
+    /// for one asset, with all three sources configured and a quorum of two.
+    fn three_sources(
+        sb_1e8: i64,
+        sb_publish_time: i64,
+        manual_cents: i64,
+    ) -> (PriceConfig, Pubkey, CollateralAsset, [TestAccount; 3]) {
+        let mut cfg = config();
+        cfg.oracle_sources = [
+            OracleSource::PythPull,
+            OracleSource::SwitchboardOnDemand,
+            OracleSource::Manual,
+        ];
+        cfg.min_oracle_sources = 2;
+        let vault = Pubkey::new_unique();
+        let asset = asset(1);
+
+        let pull = TestAccount::new(
+            PYTH_RECEIVER_PROGRAM_ID,
+            price_update(asset.feed_id, VerificationLevel::Full, NOW),
+        );
+        let mut sb = TestAccount::new(
+            SWITCHBOARD_ON_DEMAND_PROGRAM_ID,
+            switchboard_feed(sb_1e8, sb_publish_time),
+        );
+        sb.key = asset.switchboard_feed;
+        let mut manual = manual_price_for(vault);
+        manual.mint = asset.mint;
+        manual.price = manual_cents;
+        let mut data = Vec::new();
+        manual.try_serialize(&mut data).unwrap();
+        let manual = TestAccount::new(crate::ID, data);
+        (cfg, vault, asset, [pull, sb, manual])
+    }
+
+    fn median_of(sb_1e8: i64, manual_cents: i64) -> Result<Price> {
+        let (cfg, vault, asset, [mut pull, mut sb, mut manual]) =
+            three_sources(sb_1e8, NOW, manual_cents);
+        let accounts = [pull.info(), sb.info(), manual.info()];
+        Ok(load_usd_reading(&cfg, vault, &asset, &accounts, NOW)?.spot)
+    }
+
+    #[test]
+    fn median_of_agreeing_sources() {
+        // 99, 100, 101: the median is the Pyth reading
+        let spot = median_of(10_100_000_000, 9_900).unwrap();
+        assert_eq!((spot.price, spot.expo), (10_000_000_000, -8));
+    }
+
+    #[test]
+    fn median_band_outvotes_one_outlier() {
+        // 100, 101, 150: the median moves to 101 and 150 is outside the 2% band
+        let spot = median_of(10_100_000_000, 15_000).unwrap();
+        assert_eq!((spot.price, spot.expo), (10_100_000_000, -8));
+        // 50, 100, 101: the lower outlier is outvoted the same way
+        let spot = median_of(10_100_000_000, 5_000).unwrap();
+        assert_eq!(spot.price, 10_000_000_000);
+    }
+
+    #[test]
+    fn median_band_needs_a_majority() {
+        // 90, 100, 110: only the median itself is within 2% of the median
+        assert_eq!(
+            err_of(median_of(11_000_000_000, 9_000)),
+            error!(ErrorCode::OracleDeviation)
+        );
+    }
+
+    #[test]
+    fn stale_source_is_skipped() {
+        // Switchboard past the staleness window: the other two still meet the quorum
+        let (cfg, vault, asset, [mut pull, mut sb, mut manual]) =
+            three_sources(20_000_000_000, NOW - 3_600, 10_000);
+        let accounts = [pull.info(), sb.info(), manual.info()];
+        let spot = load_usd_reading(&cfg, vault, &asset, &accounts, NOW).unwrap().spot;
+        assert_eq!(spot.price, 10_000_000_000);
+
+        let mut cfg = cfg;
+        cfg.min_oracle_sources = 3;
+        assert_eq!(
+            err_of(load_usd_reading(&cfg, vault, &asset, &accounts, NOW)),
+            error!(ErrorCode::OracleQuorumNotMet)
+        );
+    }
+
+    #[test]
+    fn missing_source_fails() {
+        // Leaving the outlier's account out must not drop it from the vote
+        let (cfg, vault, asset, [mut pull, _, mut manual]) =
+            three_sources(10_000_000_000, NOW, 15_000);
+        let accounts = [pull.info(), manual.info()];
+        assert_eq!(
+            err_of(load_usd_reading(&cfg, vault, &asset, &accounts, NOW)),
+            error!(ErrorCode::PriceAccountInvalid)
+        );
+    }
+
+    #[test]
+    fn min_oracle_sources_is_a_majority() {
+        let mut cfg = config();
+        assert!(validate_price_config(&cfg).is_ok());
+        cfg.oracle_sources = [
+            OracleSource::PythPush,
+            OracleSource::PythPull,
+            OracleSource::Manual,
+        ];
+        cfg.min_oracle_sources = 1;
+        assert_eq!(
+            err_of(validate_price_config(&cfg)),
+            error!(ErrorCode::InvalidRiskParams)
+        );
+        cfg.min_oracle_sources = 2;
+        assert!(validate_price_config(&cfg).is_ok());
+        cfg.oracle_sources[2] = OracleSource::None;
+        assert!(validate_price_config(&cfg).is_ok());
+        cfg.min_oracle_sources = 1;
+        assert_eq!(
+            err_of(validate_price_config(&cfg)),
+            error!(ErrorCode::InvalidRiskParams)
+        );
+    }
+
+    /* -------------------------------- TWAP --------------------------------- */
+
+    fn observations(vault: Pubkey, mint: Pubkey) -> PriceObservations {
+        PriceObservations {
+            vault,
+            mint,
+            quote_epoch: 0,
+            head: 0,
+            len: 0,
+            observations: [PriceObservation::default(); TWAP_CAPACITY],
+            bump: 255,
+        }
+    }
+
+    #[test]
+    fn ring_buffer_wraps_oldest_first() {
+        let mut obs = observations(Pubkey::new_unique(), Pubkey::new_unique());
+        assert!(obs.newest().is_none());
+        for i in 0..(TWAP_CAPACITY as i64 + 5) {
+            obs.push(PriceObservation {
+                ts: i,
+                price: i,
+                conf: 0,
+            });
+        }
+        assert_eq!(obs.len as usize, TWAP_CAPACITY);
+        let ts: Vec<i64> = obs.chronological().map(|o| o.ts).collect();
+        let expected: Vec<i64> = (5..TWAP_CAPACITY as i64 + 5).collect();
+        assert_eq!(ts, expected);
+        assert_eq!(obs.newest().unwrap().ts, TWAP_CAPACITY as i64 + 4);
+
+        obs.clear();
+        assert!(obs.newest().is_none());
+    }
 
-#[derive(Accounts)]
-pub struct ApproveRelease<'info> {
-    #[account(mut)]
-    pub vault_state: Account<'info, VaultState>,
-    /// Any of the three role signers
-    pub role_signer: Signer<'info>,
-}
+    #[test]
+    fn twap_weights_by_time_held() {
+        let cfg = config();
+        let (vault, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut obs = observations(vault, mint);
+        // Outside the window: ignored
+        obs.push(PriceObservation { ts: NOW - 4_000, price: 1, conf: 9 });
+        obs.push(PriceObservation { ts: NOW - 300, price: 100, conf: 1 });
+        obs.push(PriceObservation { ts: NOW - 60, price: 200, conf: 3 });
+        let mut data = Vec::new();
+        obs.try_serialize(&mut data).unwrap();
+        let mut acc = TestAccount::new(crate::ID, data);
 
-#[derive(Accounts)]
-pub struct ReleaseCollateral<'info> {
-    #[account(mut)]
-    pub vault_state: Account<'info, VaultState>,
+        let twap = load_twap_price(&cfg, 0, vault, mint, &[acc.info()], NOW).unwrap();
+        // 100 for 240s, 200 for 60s
+        assert_eq!(twap.price, (100 * 240 + 200 * 60) / 300);
+        assert_eq!(twap.conf, 3);
+        assert_eq!(twap.publish_time, NOW - 60);
 
-    /// CHECK: PDA signer for vault transfers
-    #[account(
-        seeds = [SEED_AUTH, vault_state.key().as_ref()],
-        bump = vault_state.vault_authority_bump
-    )]
-    pub vault_authority: UncheckedAccount<'info>,
+        // Another vault's buffer does not count
+        assert_eq!(
+            err_of(load_twap_price(&cfg, 0, Pubkey::new_unique(), mint, &[acc.info()], NOW)),
+            error!(ErrorCode::TwapUnavailable)
+        );
+    }
 
-    #[account(
-        mut,
-        associated_token::mint = mint_account,
-        associated_token::authority = vault_authority
-    )]
-    pub vault_ata: Account<'info, TokenAccount>,
+    #[test]
+    fn twap_ignores_observations_in_an_old_quote() {
+        let cfg = config();
+        let (vault, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut obs = observations(vault, mint);
+        obs.push(PriceObservation { ts: NOW - 120, price: 100, conf: 0 });
+        obs.push(PriceObservation { ts: NOW - 60, price: 100, conf: 0 });
+        let mut data = Vec::new();
+        obs.try_serialize(&mut data).unwrap();
+        let mut acc = TestAccount::new(crate::ID, data);
 
-    #[account(
-        constraint = mint_account.key() == vault_state.mint @ ErrorCode::Unauthorized
-    )]
-    pub mint_account: Account<'info, Mint>,
+        assert!(load_twap_price(&cfg, 0, vault, mint, &[acc.info()], NOW).is_ok());
+        // The quote changed since: the buffer no longer counts until it is re-cranked
+        assert_eq!(
+            err_of(load_twap_price(&cfg, 1, vault, mint, &[acc.info()], NOW)),
+            error!(ErrorCode::TwapUnavailable)
+        );
+    }
 
-    /// Recipient owner (for event & ATA checks)
-    /// CHECK: Only used for key() in event; safety via recipient_ata checks.
-    pub recipient: UncheckedAccount<'info>,
+    /* --------------------------- Withdrawal guards -------------------------- */
 
-    #[account(
-        mut,
-        constraint = recipient_ata.owner == recipient.key(),
-        constraint = recipient_ata.mint == mint_account.key()
-    )]
-    pub recipient_ata: Account<'info, TokenAccount>,
+    #[test]
+    fn withdrawal_prices_set_the_reference() {
+        let mut state = vault(vec![asset(1), asset(0)]);
+        let prices = [Some(px(100_000_000, 0, -8)), None];
+        assert!(check_withdrawal_prices(&mut state, &prices, NOW).unwrap().is_none());
+        let a = &state.basket[0];
+        assert_eq!((a.last_price, a.last_expo, a.last_price_ts), (100_000_000, -8, NOW));
+        assert_eq!(state.basket[1].last_price_ts, 0);
+    }
 
-    /// Optional Pyth price account (required when price checks are enabled)
-    /// CHECK: read-only; validated by key equality in handler when enabled
-    pub pyth_price_acc: Option<UncheckedAccount<'info>>,
+    #[test]
+    fn withdrawal_breaker_trips_on_a_large_move() {
+        let mut state = vault(vec![asset(1)]);
+        state.basket[0].last_price = 100_000_000;
+        state.basket[0].last_expo = -8;
+        state.basket[0].last_price_ts = NOW - 600;
 
-    pub token_program: Program<'info, Token>,
-}
+        // 10% is the limit; 11% at a different exponent trips
+        let trip = check_withdrawal_prices(&mut state, &[Some(px(8_900, 0, -4))], NOW)
+            .unwrap()
+            .unwrap();
+        assert_eq!(trip.mint, state.basket[0].mint);
+        assert_eq!(trip.move_bps, 1_100);
+        // The reference is kept for the custodian's review
+        assert_eq!(state.basket[0].last_price_ts, NOW - 600);
 
-#[derive(Accounts)]
-pub struct Pause<'info> {
-    #[account(mut)]
-    pub vault_state: Account<'info, VaultState>,
-    pub custodian: Signer<'info>,
-}
+        let ok = check_withdrawal_prices(&mut state, &[Some(px(110_000_000, 0, -8))], NOW);
+        assert!(ok.unwrap().is_none());
+        assert_eq!(state.basket[0].last_price, 110_000_000);
+        assert_eq!(state.basket[0].last_price_ts, NOW);
+    }
 
-#[derive(Accounts)]
-pub struct RotateRole<'info> {
-    #[account(mut)]
-    pub vault_state: Account<'info, VaultState>,
-}
+    #[test]
+    fn withdrawal_breaker_rebaselines_after_the_window() {
+        let mut state = vault(vec![asset(1)]);
+        state.basket[0].last_price = 100_000_000;
+        state.basket[0].last_expo = -8;
+        state.basket[0].last_price_ts = NOW - state.price_config.price_move_window_secs - 1;
+        let prices = [Some(px(50_000_000, 0, -8))];
+        assert!(check_withdrawal_prices(&mut state, &prices, NOW).unwrap().is_none());
+        assert_eq!(state.basket[0].last_price, 50_000_000);
+    }
 
-#[derive(Accounts)]
-pub struct Admin<'info> {
-    #[account(mut)]
-    pub vault_state: Account<'info, VaultState>,
-    pub custodian: Signer<'info>,
-}
+    #[test]
+    fn withdrawal_prices_need_a_tight_confidence() {
+        let mut state = vault(vec![asset(1)]);
+        // 2.01% conf against a 2% limit
+        let prices = [Some(px(100_000_000, 2_010_000, -8))];
+        assert_eq!(
+            err_of(check_withdrawal_prices(&mut state, &prices, NOW)),
+            error!(ErrorCode::PriceConfidenceTooWide)
+        );
+        assert_eq!(state.basket[0].last_price_ts, 0);
+    }
 
-#[derive(Accounts)]
-pub struct CloseVault<'info> {
-    #[account(
-        mut,
-        close = recipient,
-        seeds = [
-            SEED_VAULT,
-            vault_state.mint.as_ref(),
-            vault_state.custodian.as_ref(),
-            vault_state.borrower.as_ref(),
-            vault_state.lender.as_ref()
-        ],
-        bump
-    )]
-    pub vault_state: Account<'info, VaultState>,
-    /// CHECK: rent refund target
-    #[account(mut)]
-    pub recipient: UncheckedAccount<'info>,
-    #[account(
-        mut,
-        associated_token::mint = mint_account,
-        associated_token::authority = vault_authority
-    )]
-    pub vault_ata: Account<'info, TokenAccount>,
-    #[account(
-        constraint = mint_account.key() == vault_state.mint @ ErrorCode::Unauthorized
-    )]
-    pub mint_account: Account<'info, Mint>,
-    /// CHECK: PDA signer confirmation
-    #[account(
-        seeds = [SEED_AUTH, vault_state.key().as_ref()],
-        bump = vault_state.vault_authority_bump
-    )]
-    pub vault_authority: UncheckedAccount<'info>,
-}
+    /* ------------------------------ Cross rate ----------------------------- */
 
-/* --------------------------------- State ---------------------------------- */
+    #[test]
+    fn cross_price_rounds_against_the_vault() {
+        // 1.10 USD collateral in EUR at 1.25 ± 0.01 USD
+        let out = cross_price(&px(110_000_000, 1_000_000, -8), &px(125_000_000, 1_000_000, -8))
+            .unwrap();
+        assert_eq!(out.expo, -8);
+        assert_eq!(out.price, 88_000_000);
+        // price - conf = (1.10 - 0.01) / (1.25 + 0.01), floored
+        assert_eq!(out.price - out.conf as i64, 86_507_936);
 
-#[account]
-pub struct VaultState {
-    pub mint: Pubkey,
-    pub mint_decimals: u8,
-    pub vault_authority_bump: u8,
-    pub custodian: Pubkey,
-    pub borrower: Pubkey,
-    pub lender: Pubkey,
-    pub approvals_bitmap: u8, // bit0=custodian, bit1=borrower, bit2=lender
-    pub amount_locked: u64,
-    pub is_frozen: bool,
+        // Different exponents: quote at 1e-5
+        let out = cross_price(&px(110_000_000, 0, -8), &px(125_000, 0, -5)).unwrap();
+        assert_eq!((out.price, out.conf), (88_000_000, 0));
 
-    // Governance & safety
-    pub threshold: u8,        // default 2 (2-of-3)
-    pub last_cap_reset_ts: i64,
-    pub released_today: u64,          // token units (fallback)
-    pub released_today_usd_1e6: u64,  // USD micro when Pyth enabled
+        assert_eq!(
+            err_of(cross_price(&px(110_000_000, 0, -8), &px(0, 0, -8))),
+            error!(ErrorCode::PriceNonPositive)
+        );
+    }
 
-    pub price_config: PriceConfig,
-}
+    /* ---------------------------- Manual prices ---------------------------- */
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
-pub struct PriceConfig {
-    pub enabled: bool,
-    pub sol_usdc_price_feed: Pubkey,
-    pub max_ltv_bps: u16,                 // e.g. 7000 = 70% LTV
-    pub max_single_release_usd_1e6: u64,  // per-tx cap in micro-USDC
-    pub daily_cap_usd_1e6: u64,           // daily cap in micro-USDC
-    pub max_price_staleness_secs: i64,    // price freshness window
-}
+    fn unposted_manual_price() -> ManualPrice {
+        ManualPrice {
+            price: 0,
+            expo: 0,
+            publish_time: 0,
+            ref_price: 0,
+            ref_expo: 0,
+            ref_ts: 0,
+            ..manual_price_for(Pubkey::new_unique())
+        }
+    }
 
-impl VaultState {
-    pub const SIZE: usize =
-        32 + // mint
-        1  + // mint_decimals
-        1  + // vault_authority_bump
-        32 + // custodian
-        32 + // borrower
-        32 + // lender
-        1  + // approvals_bitmap
-        8  + // amount_locked
-        1  + // is_frozen
-        1  + // threshold
-        8  + // last_cap_reset_ts
-        8  + // released_today
-        8  + // released_today_usd_1e6
-        // PriceConfig
-        1  + // enabled
-        32 + // sol_usdc_price_feed
-        2  + // max_ltv_bps
-        8  + // max_single_release_usd_1e6
-        8  + // daily_cap_usd_1e6
-        8;   // max_price_staleness_secs
-}
+    #[test]
+    fn manual_price_moves_are_bounded_by_the_reference() {
+        let cfg = config();
+        let mut m = unposted_manual_price();
+        m.post(&cfg, 100, 0, false, NOW).unwrap();
+        assert_eq!((m.ref_price, m.ref_ts), (100, NOW));
 
-/* -------------------------------- Events ---------------------------------- */
+        // 10% is allowed; at a finer exponent too
+        m.post(&cfg, 11_000, -2, false, NOW + 60).unwrap();
+        assert_eq!((m.price, m.expo), (11_000, -2));
+        // Chaining another 10% inside the window is measured against 100, not 110
+        assert_eq!(
+            err_of(m.post(&cfg, 120, 0, false, NOW + 120)),
+            error!(ErrorCode::ManualPriceChangeTooLarge)
+        );
+        assert_eq!(
+            err_of(m.post(&cfg, 89, 0, false, NOW + 120)),
+            error!(ErrorCode::ManualPriceChangeTooLarge)
+        );
+        assert_eq!(m.ref_price, 100);
+    }
 
-#[event]
-pub struct VaultInitialized {
-    pub mint: Pubkey,
-    pub custodian: Pubkey,
-    pub borrower: Pubkey,
-    pub lender: Pubkey,
-}
+    #[test]
+    fn manual_price_reference_reanchors_once_per_window() {
+        let cfg = config();
+        let mut m = unposted_manual_price();
+        m.post(&cfg, 100, 0, false, NOW).unwrap();
+        m.post(&cfg, 110, 0, false, NOW + 60).unwrap();
 
-#[event]
-pub struct CollateralDeposited {
-    pub amount: u64,
-    pub new_total: u64,
-}
+        // A new window starts from the latest price
+        let next = NOW + cfg.manual_change_window_secs;
+        m.post(&cfg, 120, 0, false, next).unwrap();
+        assert_eq!((m.ref_price, m.ref_ts), (110, next));
+        // ... and holds it for the whole window
+        assert_eq!(
+            err_of(m.post(&cfg, 125, 0, false, next + 60)),
+            error!(ErrorCode::ManualPriceChangeTooLarge)
+        );
+    }
 
-#[event]
-pub struct ReleaseApproved {
-    pub by_role: u8,
-    pub approvals_bitmap: u8,
-}
+    #[test]
+    fn manual_price_cosigned_move_becomes_the_reference() {
+        let cfg = config();
+        let mut m = unposted_manual_price();
+        m.post(&cfg, 100, 0, false, NOW).unwrap();
+        m.post(&cfg, 150, 0, true, NOW + 60).unwrap();
+        assert!(m.lender_cosigned);
+        assert_eq!((m.ref_price, m.ref_ts), (150, NOW + 60));
+        // The custodian alone now moves around 150
+        m.post(&cfg, 160, 0, false, NOW + 120).unwrap();
+        assert!(!m.lender_cosigned);
+    }
 
-#[event]
-pub struct CollateralReleased {
-    pub recipient: Pubkey,
-    pub amount: u64,
-    pub remaining: u64,
-    pub approvals_after: u8,
-}
+    /* ----------------------------- Transfer fees --------------------------- */
 
-#[event]
-pub struct Paused {}
+    /// Token-2022 mint data with room for `extensions`, which `init` fills in.
+    fn mint_2022(
+        extensions: &[spl_token_2022::extension::ExtensionType],
+        freeze_authority: Option<Pubkey>,
+        init: impl FnOnce(
+            &mut spl_token_2022::extension::StateWithExtensionsMut<spl_token_2022::state::Mint>,
+        ),
+    ) -> Vec<u8> {
+        use spl_token_2022::extension::{ExtensionType, StateWithExtensionsMut};
+        let len =
+            ExtensionType::try_calculate_account_len::<spl_token_2022::state::Mint>(extensions)
+                .unwrap();
+        let mut data = vec![0u8; len];
+        let mut state =
+            StateWithExtensionsMut::<spl_token_2022::state::Mint>::unpack_uninitialized(&mut data)
+                .unwrap();
+        init(&mut state);
+        state.base = spl_token_2022::state::Mint {
+            decimals: 6,
+            is_initialized: true,
+            freeze_authority: freeze_authority.into(),
+            ..Default::default()
+        };
+        state.pack_base();
+        state.init_account_type().unwrap();
+        data
+    }
 
-#[event]
-pub struct Unpaused {}
+    /// 1% capped at 5.000000 until epoch 10, then 2.5% capped at 1,000.000000.
+    fn fee_mint() -> TestAccount {
+        let data = mint_2022(
+            &[spl_token_2022::extension::ExtensionType::TransferFeeConfig],
+            None,
+            |state| {
+                let cfg = state.init_extension::<TransferFeeConfig>(true).unwrap();
+                cfg.older_transfer_fee.transfer_fee_basis_points = 100.into();
+                cfg.older_transfer_fee.maximum_fee = 5_000_000.into();
+                cfg.newer_transfer_fee.epoch = 10.into();
+                cfg.newer_transfer_fee.transfer_fee_basis_points = 250.into();
+                cfg.newer_transfer_fee.maximum_fee = 1_000_000_000.into();
+            },
+        );
+        TestAccount::new(spl_token_2022::ID, data)
+    }
 
-#[event]
-pub struct StateSignal {
-    pub paused: bool,
-    pub approvals_bitmap: u8,
-    pub amount_locked: u64,
-}
+    #[test]
+    fn gross_up_delivers_the_net_amount() {
+        let mut mint = fee_mint();
+        let mint = mint.info();
+        for epoch in [0, 10] {
+            for net in [1, 99, 100, 12_345, 1_000_000, 999_999_999] {
+                let gross = gross_up_for_fee(&mint, net, epoch).unwrap();
+                assert_eq!(gross - transfer_fee(&mint, gross, epoch).unwrap(), net);
+            }
+        }
+        // The older fee is capped: a large transfer only pays the 5.000000 maximum
+        assert_eq!(gross_up_for_fee(&mint, 10_000_000_000, 0).unwrap(), 10_005_000_000);
+        assert_eq!(transfer_fee(&mint, 1_000_000, 10).unwrap(), 25_000);
+    }
 
-#[event]
-pub struct PriceUsed {
-    pub feed: Pubkey,
-    pub price: i64,
-    pub conf: u64,
-    pub expo: i32,
-    pub publish_time: i64,
-}
+    #[test]
+    fn gross_up_without_a_fee_is_the_identity() {
+        let mut spl = TestAccount::new(anchor_spl::token::ID, mint_2022(&[], None, |_| {}));
+        assert_eq!(gross_up_for_fee(&spl.info(), 1_000, 0).unwrap(), 1_000);
+        assert_eq!(transfer_fee(&spl.info(), 1_000, 0).unwrap(), 0);
+    }
 
-/* ------------------------------- Error Codes ------------------------------ */
+    /* ------------------------------ Mint policy ---------------------------- */
 
-#[error_code]
-pub enum ErrorCode {
-    #[msg("Invalid role")]
-    InvalidRole,
-    #[msg("Roles must be distinct")]
-    RoleNotDistinct,
-    #[msg("This action is unauthorized")]
-    Unauthorized,
-    #[msg("Not enough approvals (need at least threshold)")]
-    NotEnoughApprovals,
-    #[msg("Vault is paused")]
-    Paused,
-    #[msg("Release amount exceeds locked total or cap")]
-    AmountExceedsLocked,
-    #[msg("Math overflow")]
-    MathOverflow,
-    #[msg("Invalid amount")]
-    InvalidAmount,
-    #[msg("Internal: bump not found")]
-    BumpNotFound,
-    #[msg("Per-day cap exceeded")]
-    DailyCapExceeded,
-    #[msg("Cannot deposit while a release flow is pending (approvals exist)")]
-    PendingReleaseFlow,
-    #[msg("Pyth price account invalid")]
-    PriceAccountInvalid,
-    #[msg("Price is stale")]
-    PriceStale,
-    #[msg("Non-positive price")]
-    PriceNonPositive,
-    #[msg("USD cap exceeded")]
-    UsdCapExceeded,
-    #[msg("LTV check failed")]
-    LtvBreach,
-    #[msg("Invalid risk parameters")]
-    InvalidRiskParams,
-}
+    #[test]
+    fn mint_risks_flags_freeze_authority() {
+        let mut plain = TestAccount::new(anchor_spl::token::ID, mint_2022(&[], None, |_| {}));
+        assert_eq!(mint_risks(&plain.info()).unwrap(), 0);
+        let freezable = mint_2022(&[], Some(Pubkey::new_unique()), |_| {});
+        let mut spl = TestAccount::new(anchor_spl::token::ID, freezable.clone());
+        assert_eq!(mint_risks(&spl.info()).unwrap(), MINT_RISK_FREEZE_AUTHORITY);
+        let mut t22 = TestAccount::new(spl_token_2022::ID, freezable);
+        assert_eq!(mint_risks(&t22.info()).unwrap(), MINT_RISK_FREEZE_AUTHORITY);
+    }
 
-/* ------------------------------- Utilities -------------------------------- */
+    #[test]
+    fn mint_risks_flags_token_2022_extensions() {
+        use spl_token_2022::extension::ExtensionType;
+        let extensions = [
+            ExtensionType::PermanentDelegate,
+            ExtensionType::DefaultAccountState,
+            ExtensionType::NonTransferable,
+        ];
+        let risky = mint_2022(&extensions, None, |state| {
+            state.init_extension::<PermanentDelegate>(true).unwrap().delegate =
+                Some(Pubkey::new_unique()).try_into().unwrap();
+            state.init_extension::<DefaultAccountState>(true).unwrap().state =
+                AccountState::Frozen as u8;
+            state.init_extension::<NonTransferable>(true).unwrap();
+        });
+        let mut mint = TestAccount::new(spl_token_2022::ID, risky);
+        assert_eq!(
+            mint_risks(&mint.info()).unwrap(),
+            MINT_RISK_PERMANENT_DELEGATE | MINT_RISK_DEFAULT_FROZEN | MINT_RISK_NON_TRANSFERABLE
+        );
+        assert_eq!(
+            err_of(check_mint_policy(MINT_RISK_PERMANENT_DELEGATE, &mint.info())),
+            error!(ErrorCode::MintPolicyViolation)
+        );
+        assert!(check_mint_policy(MINT_RISK_ALL, &mint.info()).is_ok());
 
-#[inline]
-fn has_bit(bitmap: u8, idx: u8) -> bool {
-    ((bitmap >> idx) & 1) == 1
-}
+        // Extensions present but inert: no delegate, accounts start initialized
+        let inert = mint_2022(&extensions[..2], None, |state| {
+            state.init_extension::<PermanentDelegate>(true).unwrap();
+            state.init_extension::<DefaultAccountState>(true).unwrap().state =
+                AccountState::Initialized as u8;
+        });
+        let mut mint = TestAccount::new(spl_token_2022::ID, inert);
+        assert_eq!(mint_risks(&mint.info()).unwrap(), 0);
+    }
 
-#[inline]
-fn set_bit(bitmap: &mut u8, idx: u8) {
-    *bitmap |= 1 << idx;
-}
+    /* --------------------------- UI amount mints --------------------------- */
 
-#[inline]
-fn clear_bit(bitmap: &mut u8, idx: u8) {
-    *bitmap &= !(1 << idx);
-}
+    /// Token-2022 mint carrying a scaled UI amount entry, written as raw TLV since
+    /// spl-token-2022 0.9 has no type for it.
+    fn scaled_ui_mint(multiplier: f64, effective_ts: i64, new_multiplier: f64) -> TestAccount {
+        use anchor_lang::solana_program::program_pack::Pack;
+        let mut data = vec![0u8; MINT_TLV_START];
+        spl_token_2022::state::Mint {
+            decimals: 6,
+            is_initialized: true,
+            ..Default::default()
+        }
+        .pack_into_slice(&mut data[..spl_token_2022::state::Mint::LEN]);
+        data[MINT_TLV_START - 1] = MINT_ACCOUNT_TYPE;
+        data.extend_from_slice(&EXT_SCALED_UI_AMOUNT.to_le_bytes());
+        data.extend_from_slice(&56u16.to_le_bytes());
+        data.extend_from_slice(&[0; 32]); // authority
+        data.extend_from_slice(&multiplier.to_le_bytes());
+        data.extend_from_slice(&effective_ts.to_le_bytes());
+        data.extend_from_slice(&new_multiplier.to_le_bytes());
+        TestAccount::new(spl_token_2022::ID, data)
+    }
 
-#[inline]
-fn bitcount(bitmap: u8) -> u32 {
-    bitmap.count_ones()
-}
+    #[test]
+    fn ui_multiplier_interest_bearing() {
+        // 5% since initialization, no rate update
+        let data = mint_2022(
+            &[spl_token_2022::extension::ExtensionType::InterestBearingConfig],
+            None,
+            |state| {
+                let cfg = state.init_extension::<InterestBearingConfig>(true).unwrap();
+                cfg.initialization_timestamp = 0.into();
+                cfg.pre_update_average_rate = 500.into();
+                cfg.last_update_timestamp = 0.into();
+                cfg.current_rate = 500.into();
+            },
+        );
+        let mut mint = TestAccount::new(spl_token_2022::ID, data);
+        assert!(mint_ui_scaled(&mint.info()).unwrap());
 
-#[inline]
-fn is_role(state: &VaultState, k: Pubkey) -> bool {
-    k == state.custodian || k == state.borrower || k == state.lender
-}
+        let year = INTEREST_BEARING_SECS_PER_YEAR as i64;
+        let m = ui_multiplier(&mint.info(), year).unwrap();
+        assert!((m - 0.05f64.exp()).abs() < 1e-12);
+        assert_eq!(ui_multiplier(&mint.info(), 0).unwrap(), 1.0);
+    }
 
-/// 10^exp as u128 (exp must be >= 0 and small enough to fit)
-#[inline]
-fn ten_pow_u128(exp: i32) -> Option<u128> {
-    if exp < 0 {
-        return None;
+    #[test]
+    fn ui_multiplier_scaled_ui_amount() {
+        let mut mint = scaled_ui_mint(1.5, NOW, 3.0);
+        assert!(mint_ui_scaled(&mint.info()).unwrap());
+        assert_eq!(ui_multiplier(&mint.info(), NOW - 1).unwrap(), 1.5);
+        assert_eq!(ui_multiplier(&mint.info(), NOW).unwrap(), 3.0);
+
+        let mut plain = TestAccount::new(spl_token_2022::ID, mint_2022(&[], None, |_| {}));
+        assert!(!mint_ui_scaled(&plain.info()).unwrap());
     }
-    let mut v: u128 = 1;
-    for _ in 0..(exp as u32) {
-        v = v.checked_mul(10)?;
+
+    #[test]
+    fn ui_price_prefers_named_mint() {
+        let mut mint = scaled_ui_mint(2.0, i64::MAX, 2.0);
+        let mut a = asset(1);
+        a.mint = mint.key;
+        a.ui_scaled = true;
+
+        let out = ui_price(&a, px(100, 3, -2), &[mint.info()], &[], NOW).unwrap();
+        assert_eq!((out.price, out.conf), (200, 6));
+        assert_eq!(
+            err_of(ui_price(&a, px(100, 3, -2), &[], &[], NOW)),
+            error!(ErrorCode::MintAccountMissing)
+        );
     }
-    Some(v)
 }
-